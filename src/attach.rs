@@ -0,0 +1,121 @@
+use std::io::{Read, Write};
+use std::os::fd::{AsFd, AsRawFd};
+use std::os::unix::net::UnixStream;
+
+use log::error;
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+
+use crate::AttachCommand;
+use crate::error::{Context, Error, Result};
+use crate::container::{
+    console_socket_path, encode_frame, get_command, get_winsize, is_running, resolve_container_id, unwatch_window_size,
+    watch_window_size, window_changed, RawTerminal, FRAME_RESIZE, FRAME_STDIN,
+};
+
+// 分离快捷键 Ctrl-P Ctrl-Q：断开 attach，容器继续在后台运行
+const DETACH_KEYS: [u8; 2] = [0x10, 0x11];
+
+pub fn attach(command: AttachCommand) -> Result<()> {
+    let container_id = resolve_container_id(&command.container_id)?;
+    if !is_running(&container_id)? {
+        return Err(Error::Conflict(format!("Container {} is not running", container_id)));
+    }
+
+    // 只有后台运行的容器才有 console server
+    let mut stream = UnixStream::connect(console_socket_path(&container_id))
+        .context(format!("Container {} is not attachable", container_id))?;
+
+    let tty = get_command(&container_id)?.tty;
+    let raw = if tty { Some(RawTerminal::new()) } else { None };
+    if tty {
+        watch_window_size();
+        send_winsize(&mut stream);
+    }
+
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    let mut stdin_open = true;
+    let mut escape_pending = false;    // 上一个字节是否为 Ctrl-P
+    let mut detached = false;
+    let mut buf = [0u8; 4096];
+
+    'proxy: loop {
+        if tty && window_changed() {
+            send_winsize(&mut stream);
+        }
+
+        let mut fds = vec![PollFd::new(stream.as_fd(), PollFlags::POLLIN)];
+        if stdin_open {
+            fds.push(PollFd::new(stdin.as_fd(), PollFlags::POLLIN));
+        }
+        match poll(&mut fds, PollTimeout::NONE) {
+            Ok(_) => {},
+            Err(Errno::EINTR) => continue,
+            Err(e) => {
+                error!("poll failed: {}", e);
+                break;
+            }
+        }
+        let ready = PollFlags::POLLIN | PollFlags::POLLHUP | PollFlags::POLLERR;
+        let stream_ready = fds[0].revents().unwrap_or(PollFlags::empty()).intersects(ready);
+        let stdin_ready = stdin_open && fds[1].revents().unwrap_or(PollFlags::empty()).intersects(ready);
+        drop(fds);
+
+        if stdin_ready {
+            match nix::unistd::read(stdin.as_raw_fd(), &mut buf) {
+                Err(Errno::EINTR) => {},
+                Ok(0) | Err(_) => stdin_open = false,
+                Ok(n) => {
+                    let mut data = Vec::with_capacity(n + 1);
+                    for &b in &buf[..n] {
+                        if escape_pending {
+                            escape_pending = false;
+                            if b == DETACH_KEYS[1] {
+                                detached = true;
+                                break 'proxy;
+                            }
+                            data.push(DETACH_KEYS[0]);
+                        }
+                        if b == DETACH_KEYS[0] {
+                            escape_pending = true;
+                        } else {
+                            data.push(b);
+                        }
+                    }
+                    if !data.is_empty() && stream.write_all(&encode_frame(FRAME_STDIN, &data)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if stream_ready {
+            // console server 关闭连接说明容器已经退出
+            match stream.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let _ = stdout.write_all(&buf[..n]);
+                    let _ = stdout.flush();
+                }
+            }
+        }
+    }
+
+    if tty {
+        unwatch_window_size();
+    }
+    drop(raw);
+    if detached {
+        println!("Detached from container {}", container_id);
+    }
+    Ok(())
+}
+
+fn send_winsize(stream: &mut UnixStream) {
+    if let Some(ws) = get_winsize(std::io::stdin().as_fd()) {
+        let mut payload = ws.ws_row.to_be_bytes().to_vec();
+        payload.extend_from_slice(&ws.ws_col.to_be_bytes());
+        let _ = stream.write_all(&encode_frame(FRAME_RESIZE, &payload));
+    }
+}
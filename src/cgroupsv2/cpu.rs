@@ -1,4 +1,5 @@
-use super::manager::{CGroupIf, ResourceConfig, CGROUP_ROOTPATH};
+use super::manager::{CGroupIf, ResourceConfig};
+use crate::error::{Context, Result};
 
 pub struct CGroupCPU {
 
@@ -11,14 +12,18 @@ impl CGroupCPU {
 }
 
 impl CGroupIf for CGroupCPU {
-    fn set(&self, path: &String, resource_config: &ResourceConfig) {
+    fn controller(&self, resource_config: &ResourceConfig) -> Option<&'static str> {
+        resource_config.cpu.map(|_| "cpu")
+    }
+
+    fn set(&self, path: &str, resource_config: &ResourceConfig) -> Result<()> {
         let cpu_limit = if let Some(cpu) = resource_config.cpu {
             cpu
         } else {
-            return;
+            return Ok(());
         };
-        let cpu_path = format!("{}/{}/cpu.max", CGROUP_ROOTPATH, path);
+        let cpu_path = format!("{}/cpu.max", path);
         let cpu_limit_str = format!("{} 100000", cpu_limit * 1000);
-        std::fs::write(&cpu_path, cpu_limit_str).expect("Failed to set CPU limit");
+        std::fs::write(&cpu_path, cpu_limit_str).context(format!("Failed to set CPU limit in {}", cpu_path))
     }
-}
\ No newline at end of file
+}
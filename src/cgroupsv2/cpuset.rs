@@ -0,0 +1,57 @@
+use super::manager::{CGroupIf, ResourceConfig};
+use crate::error::{Context, Error, Result};
+
+pub struct CGroupCpuset {
+
+}
+
+impl CGroupCpuset {
+    pub fn new() -> Self {
+        CGroupCpuset {}
+    }
+}
+
+impl CGroupIf for CGroupCpuset {
+    fn controller(&self, resource_config: &ResourceConfig) -> Option<&'static str> {
+        (resource_config.cpuset_cpus.is_some() || resource_config.cpuset_mems.is_some()).then_some("cpuset")
+    }
+
+    fn set(&self, path: &str, resource_config: &ResourceConfig) -> Result<()> {
+        for (file, list) in [("cpuset.cpus", &resource_config.cpuset_cpus), ("cpuset.mems", &resource_config.cpuset_mems)] {
+            let Some(list) = list else {
+                continue;
+            };
+            validate_list(list)?;
+            let cpuset_path = format!("{}/{}", path, file);
+            std::fs::write(&cpuset_path, list).context(format!("Failed to set {} in {}", list, cpuset_path))?;
+        }
+        Ok(())
+    }
+}
+
+// CPU 和内存节点的列表形如 0-3,5，内核写入失败时的错误不够明确，先检查格式
+fn validate_list(list: &str) -> Result<()> {
+    let valid = list.split(',').all(|range| {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        matches!((start.parse::<u32>(), end.parse::<u32>()), (Ok(start), Ok(end)) if start <= end)
+    });
+    if !valid {
+        return Err(Error::InvalidArgument(format!("Invalid cpuset {}, expected a list such as 0-3,5", list)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_lists() {
+        for list in ["0", "0-3", "0-3,5", "1,3,5-7", "2-2"] {
+            assert!(validate_list(list).is_ok(), "{:?} should be accepted", list);
+        }
+        for list in ["", "a", "3-1", "0-", "-1", "0,,1", "0-3,", "0 - 3", "0-1-2"] {
+            assert!(validate_list(list).is_err(), "{:?} should be rejected", list);
+        }
+    }
+}
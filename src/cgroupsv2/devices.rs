@@ -0,0 +1,332 @@
+use libc::{syscall, SYS_bpf};
+use log::info;
+use nix::errno::Errno;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+use super::manager::{CGroupIf, ResourceConfig};
+use crate::error::{Context, Error, Result};
+use crate::state::is_rootless;
+
+// cgroup v2 没有 devices.allow 这样的接口文件，要把 BPF_PROG_TYPE_CGROUP_DEVICE 程序挂到 cgroup 上
+// 容器中的进程每次打开或 mknod 设备时内核都会执行这个程序，返回 0 表示拒绝
+pub struct CGroupDevices {
+
+}
+
+impl CGroupDevices {
+    pub fn new() -> Self {
+        CGroupDevices {}
+    }
+}
+
+// 设备访问规则，与 docker 的 --device-cgroup-rule 格式相同：类型 major:minor 权限，如 c 1:3 rwm
+// kind 为 None 表示任意类型（a），major、minor 为 None 表示任意编号（*）
+#[derive(Clone, PartialEq)]
+pub struct DeviceRule {
+    pub kind: Option<char>,
+    pub major: Option<u32>,
+    pub minor: Option<u32>,
+    pub access: String,
+}
+
+// runc 和 docker 默认允许的设备：任意设备的 mknod、默认创建的设备、/dev/console、/dev/pts 下的终端、ptmx 和 tun
+const DEFAULT_RULES: [&str; 12] = [
+    "c *:* m", "b *:* m",
+    "c 1:3 rwm", "c 1:5 rwm", "c 1:7 rwm", "c 1:8 rwm", "c 1:9 rwm", "c 5:0 rwm",
+    "c 5:1 rwm", "c 136:* rwm", "c 5:2 rwm", "c 10:200 rwm",
+];
+
+impl DeviceRule {
+    pub fn parse(rule: &str) -> Result<Self> {
+        let invalid = || Error::InvalidArgument(format!("Invalid device cgroup rule {:?}, expected 'type major:minor access'", rule));
+        let fields: Vec<&str> = rule.split_whitespace().collect();
+        let [kind, numbers, access] = fields[..] else {
+            return Err(invalid());
+        };
+        let kind = match kind {
+            "a" => None,
+            "c" | "b" => kind.chars().next(),
+            _ => return Err(invalid()),
+        };
+        let (major, minor) = numbers.split_once(':').ok_or_else(invalid)?;
+        let parse_number = |number: &str| match number {
+            "*" => Ok(None),
+            _ => number.parse().map(Some).map_err(|_| invalid()),
+        };
+        if access.is_empty() || !access.chars().all(|c| "rwm".contains(c)) {
+            return Err(invalid());
+        }
+        Ok(DeviceRule { kind, major: parse_number(major)?, minor: parse_number(minor)?, access: access.to_string() })
+    }
+
+    pub fn default_rules() -> Vec<Self> {
+        DEFAULT_RULES.iter().map(|rule| Self::parse(rule).expect("invalid default device rule")).collect()
+    }
+}
+
+impl CGroupIf for CGroupDevices {
+    // 设备程序直接挂到 cgroup 上，不需要开启控制器
+    fn controller(&self, _resource_config: &ResourceConfig) -> Option<&'static str> {
+        None
+    }
+
+    fn set(&self, path: &str, resource_config: &ResourceConfig) -> Result<()> {
+        let Some(rules) = &resource_config.devices else {
+            return Ok(());
+        };
+        // 加载 cgroup 设备程序需要宿主机上的 CAP_SYS_ADMIN，rootless 容器只能访问用户自己有权限的设备
+        if is_rootless() {
+            info!("Device cgroup rules are not enforced in rootless mode");
+            return Ok(());
+        }
+        let program = load_program(&compile(rules))?;
+        let cgroup = std::fs::File::open(path).context(format!("Failed to open cgroup {}", path))?;
+        // 程序挂载到 cgroup 上之后由内核持有，关闭 fd 不影响
+        attach_program(&program, &cgroup)
+    }
+}
+
+// struct bpf_insn
+#[repr(C)]
+struct BpfInsn {
+    code: u8,
+    regs: u8,   // 低 4 位为 dst_reg，高 4 位为 src_reg
+    off: i16,
+    imm: i32,
+}
+
+// 用到的 eBPF 指令
+const BPF_LDX_MEM_W: u8 = 0x61;     // dst = *(u32 *)(src + off)
+const BPF_ALU32_AND_K: u8 = 0x54;   // dst &= imm
+const BPF_ALU32_RSH_K: u8 = 0x74;   // dst >>= imm
+const BPF_ALU32_MOV_X: u8 = 0xbc;   // dst = src
+const BPF_ALU64_MOV_K: u8 = 0xb7;   // dst = imm
+const BPF_JMP_JNE_K: u8 = 0x55;     // if dst != imm goto pc + off
+const BPF_EXIT: u8 = 0x95;
+
+// struct bpf_cgroup_dev_ctx 中的取值
+const BPF_DEVCG_DEV_BLOCK: i32 = 1;
+const BPF_DEVCG_DEV_CHAR: i32 = 2;
+const BPF_DEVCG_ACC_MKNOD: i32 = 1;
+const BPF_DEVCG_ACC_READ: i32 = 2;
+const BPF_DEVCG_ACC_WRITE: i32 = 4;
+
+const BPF_PROG_LOAD: i32 = 5;
+const BPF_PROG_ATTACH: i32 = 8;
+const BPF_PROG_TYPE_CGROUP_DEVICE: u32 = 15;
+const BPF_CGROUP_DEVICE: u32 = 6;
+const BPF_F_ALLOW_MULTI: u32 = 2;
+
+fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> BpfInsn {
+    BpfInsn { code, regs: (src << 4) | dst, off, imm }
+}
+
+fn access_mask(access: &str) -> i32 {
+    access.chars().fold(0, |mask, c| mask | match c {
+        'r' => BPF_DEVCG_ACC_READ,
+        'w' => BPF_DEVCG_ACC_WRITE,
+        _ => BPF_DEVCG_ACC_MKNOD,
+    })
+}
+
+// 生成的程序按顺序检查每条规则，第一条匹配的规则允许访问，都不匹配时拒绝
+// r1 为 struct bpf_cgroup_dev_ctx { u32 access_type; u32 major; u32 minor; }，access_type 的低 16 位为设备类型、高 16 位为访问方式
+fn compile(rules: &[DeviceRule]) -> Vec<BpfInsn> {
+    let mut program = vec![
+        insn(BPF_LDX_MEM_W, 2, 1, 0, 0),
+        insn(BPF_ALU32_AND_K, 2, 0, 0, 0xffff),     // r2 = 设备类型
+        insn(BPF_LDX_MEM_W, 3, 1, 0, 0),
+        insn(BPF_ALU32_RSH_K, 3, 0, 0, 16),         // r3 = 访问方式
+        insn(BPF_LDX_MEM_W, 4, 1, 4, 0),            // r4 = major
+        insn(BPF_LDX_MEM_W, 5, 1, 8, 0),            // r5 = minor
+    ];
+    for rule in rules {
+        // 每个条件不满足时跳到下一条规则，跳转的距离在规则生成完之后回填
+        let mut checks = vec![];
+        if let Some(kind) = rule.kind {
+            let kind = if kind == 'b' { BPF_DEVCG_DEV_BLOCK } else { BPF_DEVCG_DEV_CHAR };
+            checks.push(insn(BPF_JMP_JNE_K, 2, 0, 0, kind));
+        }
+        let access = access_mask(&rule.access);
+        if access != BPF_DEVCG_ACC_MKNOD | BPF_DEVCG_ACC_READ | BPF_DEVCG_ACC_WRITE {
+            // 请求的访问方式必须都在规则允许的范围内
+            checks.push(insn(BPF_ALU32_MOV_X, 1, 3, 0, 0));
+            checks.push(insn(BPF_ALU32_AND_K, 1, 0, 0, !access));
+            checks.push(insn(BPF_JMP_JNE_K, 1, 0, 0, 0));
+        }
+        if let Some(major) = rule.major {
+            checks.push(insn(BPF_JMP_JNE_K, 4, 0, 0, major as i32));
+        }
+        if let Some(minor) = rule.minor {
+            checks.push(insn(BPF_JMP_JNE_K, 5, 0, 0, minor as i32));
+        }
+        checks.push(insn(BPF_ALU64_MOV_K, 0, 0, 0, 1));
+        checks.push(insn(BPF_EXIT, 0, 0, 0, 0));
+        let len = checks.len();
+        for (i, check) in checks.iter_mut().enumerate() {
+            if check.code == BPF_JMP_JNE_K {
+                check.off = (len - i - 1) as i16;
+            }
+        }
+        program.extend(checks);
+    }
+    program.push(insn(BPF_ALU64_MOV_K, 0, 0, 0, 0));
+    program.push(insn(BPF_EXIT, 0, 0, 0, 0));
+    program
+}
+
+// union bpf_attr 中 BPF_PROG_LOAD 用到的部分
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+}
+
+// union bpf_attr 中 BPF_PROG_ATTACH 用到的部分
+#[repr(C)]
+struct ProgAttachAttr {
+    target_fd: u32,
+    attach_bpf_fd: u32,
+    attach_type: u32,
+    attach_flags: u32,
+}
+
+fn load_program(program: &[BpfInsn]) -> Result<OwnedFd> {
+    let license = c"Apache";
+    let mut log = vec![0u8; 64 * 1024];
+    let mut name = [0u8; 16];
+    name[..8].copy_from_slice(b"mydocker");
+    let attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_CGROUP_DEVICE,
+        insn_cnt: program.len() as u32,
+        insns: program.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 1,
+        log_size: log.len() as u32,
+        log_buf: log.as_mut_ptr() as u64,
+        prog_name: name,
+        ..Default::default()
+    };
+    let fd = unsafe { syscall(SYS_bpf, BPF_PROG_LOAD, &attr, size_of::<ProgLoadAttr>()) };
+    if fd < 0 {
+        let errno = Errno::last();
+        // 校验器的日志说明程序被拒绝的原因
+        let log = String::from_utf8_lossy(&log);
+        let log = log.trim_end_matches('\0').trim();
+        return Err(Error::Runtime(format!("Failed to load device cgroup program: {}{}{}",
+            errno, if log.is_empty() { "" } else { "\n" }, log)));
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+fn attach_program(program: &OwnedFd, cgroup: &std::fs::File) -> Result<()> {
+    let attr = ProgAttachAttr {
+        target_fd: cgroup.as_raw_fd() as u32,
+        attach_bpf_fd: program.as_raw_fd() as u32,
+        attach_type: BPF_CGROUP_DEVICE,
+        attach_flags: BPF_F_ALLOW_MULTI,
+    };
+    if unsafe { syscall(SYS_bpf, BPF_PROG_ATTACH, &attr, size_of::<ProgAttachAttr>()) } < 0 {
+        return Err(Errno::last()).context("Failed to attach device cgroup program");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 解释执行生成的设备程序，返回 1 表示允许；只支持 compile 用到的指令
+    fn run(program: &[BpfInsn], kind: i32, access: i32, major: u32, minor: u32) -> u64 {
+        let ctx = [(kind | (access << 16)) as u32, major, minor];
+        let mut regs = [0u64; 11];
+        let mut pc = 0usize;
+        loop {
+            let insn = &program[pc];
+            let (dst, src) = ((insn.regs & 0xf) as usize, (insn.regs >> 4) as usize);
+            pc += 1;
+            match insn.code {
+                BPF_LDX_MEM_W => {
+                    assert_eq!(src, 1, "only loads from the context are supported");
+                    regs[dst] = ctx[insn.off as usize / 4] as u64;
+                }
+                BPF_ALU32_AND_K => regs[dst] = (regs[dst] as u32 & insn.imm as u32) as u64,
+                BPF_ALU32_RSH_K => regs[dst] = (regs[dst] as u32 >> insn.imm) as u64,
+                BPF_ALU32_MOV_X => regs[dst] = regs[src] as u32 as u64,
+                BPF_ALU64_MOV_K => regs[dst] = insn.imm as i64 as u64,
+                BPF_JMP_JNE_K => {
+                    if regs[dst] != insn.imm as i64 as u64 {
+                        pc += insn.off as usize;
+                    }
+                }
+                BPF_EXIT => return regs[0],
+                code => panic!("unsupported instruction {:#x}", code),
+            }
+        }
+    }
+
+    const READ: i32 = BPF_DEVCG_ACC_READ;
+    const WRITE: i32 = BPF_DEVCG_ACC_WRITE;
+    const MKNOD: i32 = BPF_DEVCG_ACC_MKNOD;
+    const CHAR: i32 = BPF_DEVCG_DEV_CHAR;
+    const BLOCK: i32 = BPF_DEVCG_DEV_BLOCK;
+
+    fn rules(rules: &[&str]) -> Vec<DeviceRule> {
+        rules.iter().map(|rule| DeviceRule::parse(rule).unwrap()).collect()
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule = DeviceRule::parse("c 1:3 rwm").unwrap();
+        assert!(rule == DeviceRule { kind: Some('c'), major: Some(1), minor: Some(3), access: "rwm".to_string() });
+        let rule = DeviceRule::parse("a *:* r").unwrap();
+        assert!(rule == DeviceRule { kind: None, major: None, minor: None, access: "r".to_string() });
+        let rule = DeviceRule::parse("b 8:* rw").unwrap();
+        assert!(rule.kind == Some('b') && rule.major == Some(8) && rule.minor.is_none());
+        for invalid in ["", "c 1:3", "x 1:3 r", "c 1 r", "c a:3 r", "c 1:3 rx", "c 1:3 ", "c 1:3 r extra"] {
+            assert!(DeviceRule::parse(invalid).is_err(), "{:?} should be rejected", invalid);
+        }
+        assert_eq!(DeviceRule::default_rules().len(), DEFAULT_RULES.len());
+    }
+
+    #[test]
+    fn default_rules_allow_only_default_devices() {
+        let program = compile(&DeviceRule::default_rules());
+        assert_eq!(run(&program, CHAR, READ | WRITE, 1, 3), 1);        // /dev/null
+        assert_eq!(run(&program, CHAR, READ, 136, 7), 1);               // /dev/pts/7
+        assert_eq!(run(&program, CHAR, MKNOD, 4, 1), 1);                // 任意字符设备都可以 mknod
+        assert_eq!(run(&program, BLOCK, MKNOD, 8, 0), 1);
+        assert_eq!(run(&program, BLOCK, READ, 8, 0), 0);                // /dev/sda
+        assert_eq!(run(&program, CHAR, READ, 1, 1), 0);                 // /dev/mem
+        assert_eq!(run(&program, CHAR, READ | MKNOD, 4, 1), 0);
+    }
+
+    #[test]
+    fn access_must_be_covered_by_one_rule() {
+        let program = compile(&rules(&["c 10:200 r", "b *:* w"]));
+        assert_eq!(run(&program, CHAR, READ, 10, 200), 1);
+        assert_eq!(run(&program, CHAR, WRITE, 10, 200), 0);
+        assert_eq!(run(&program, CHAR, READ | WRITE, 10, 200), 0);
+        assert_eq!(run(&program, CHAR, READ, 10, 201), 0);
+        assert_eq!(run(&program, BLOCK, WRITE, 8, 1), 1);
+        assert_eq!(run(&program, BLOCK, READ, 8, 1), 0);
+    }
+
+    #[test]
+    fn wildcard_type_matches_both_kinds() {
+        let program = compile(&rules(&["a 7:* rwm"]));
+        assert_eq!(run(&program, CHAR, READ | WRITE | MKNOD, 7, 0), 1);
+        assert_eq!(run(&program, BLOCK, READ, 7, 9), 1);
+        assert_eq!(run(&program, BLOCK, READ, 8, 9), 0);
+        assert_eq!(run(&compile(&[]), CHAR, READ, 1, 3), 0);
+    }
+}
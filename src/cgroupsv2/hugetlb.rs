@@ -0,0 +1,60 @@
+use super::manager::{CGroupIf, ResourceConfig};
+use super::memory::parse_memory;
+use crate::error::{Context, Error, Result};
+
+pub struct CGroupHugetlb {
+
+}
+
+impl CGroupHugetlb {
+    pub fn new() -> Self {
+        CGroupHugetlb {}
+    }
+}
+
+impl CGroupIf for CGroupHugetlb {
+    fn controller(&self, resource_config: &ResourceConfig) -> Option<&'static str> {
+        (!resource_config.hugetlb.is_empty()).then_some("hugetlb")
+    }
+
+    fn set(&self, path: &str, resource_config: &ResourceConfig) -> Result<()> {
+        for limit in &resource_config.hugetlb {
+            let (page_size, max) = parse_hugetlb(limit)?;
+            // 内核不支持的页大小没有对应的接口文件
+            let hugetlb_path = format!("{}/hugetlb.{}.max", path, page_size);
+            std::fs::write(&hugetlb_path, max.to_string())
+                .context(format!("Failed to set hugetlb limit in {}, is the page size {} supported?", hugetlb_path, page_size))?;
+        }
+        Ok(())
+    }
+}
+
+// --hugetlb-limit 形如 2MB:1g，页大小与内核接口文件中的写法相同（如 2MB、1GB），限制的写法与 --mem 相同
+fn parse_hugetlb(limit: &str) -> Result<(&str, u64)> {
+    let invalid = || Error::InvalidArgument(format!("Invalid hugetlb limit {}, expected <page size>:<limit> such as 2MB:1g", limit));
+    let (page_size, max) = limit.split_once(':').ok_or_else(invalid)?;
+    let number = ["KB", "MB", "GB"].iter().find_map(|unit| page_size.strip_suffix(unit)).ok_or_else(invalid)?;
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    Ok((page_size, parse_memory(max)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_page_size_and_limit() {
+        assert_eq!(parse_hugetlb("2MB:1g").unwrap(), ("2MB", 1 << 30));
+        assert_eq!(parse_hugetlb("1GB:2G").unwrap(), ("1GB", 2 << 30));
+        assert_eq!(parse_hugetlb("64KB:512k").unwrap(), ("64KB", 512 << 10));
+    }
+
+    #[test]
+    fn rejects_invalid_limits() {
+        for limit in ["", "2MB", "2MB:", ":1g", "MB:1g", "2mb:1g", "2M:1g", "2 MB:1g", "-2MB:1g", "2MB:1", "2MB:1t"] {
+            assert!(parse_hugetlb(limit).is_err(), "{:?} should be rejected", limit);
+        }
+    }
+}
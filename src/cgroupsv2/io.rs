@@ -0,0 +1,91 @@
+use nix::sys::stat::{major, minor, stat, SFlag};
+
+use super::manager::{CGroupIf, ResourceConfig};
+use super::memory::parse_memory;
+use crate::error::{Context, Error, Result};
+
+pub struct CGroupIO {
+
+}
+
+impl CGroupIO {
+    pub fn new() -> Self {
+        CGroupIO {}
+    }
+}
+
+impl CGroupIf for CGroupIO {
+    fn controller(&self, resource_config: &ResourceConfig) -> Option<&'static str> {
+        let requested = resource_config.blkio_weight.is_some()
+            || !resource_config.device_read_bps.is_empty()
+            || !resource_config.device_write_bps.is_empty();
+        requested.then_some("io")
+    }
+
+    fn set(&self, path: &str, resource_config: &ResourceConfig) -> Result<()> {
+        if let Some(weight) = resource_config.blkio_weight {
+            let weight_path = format!("{}/io.weight", path);
+            std::fs::write(&weight_path, format!("default {}", io_weight(weight)?))
+                .context(format!("Failed to set IO weight in {}", weight_path))?;
+        }
+        // io.max 每行只修改写入的那一项，读写的限制可以分开写
+        let max_path = format!("{}/io.max", path);
+        for (key, limits) in [("rbps", &resource_config.device_read_bps), ("wbps", &resource_config.device_write_bps)] {
+            for limit in limits {
+                let (device, rate) = parse_device_rate(limit)?;
+                std::fs::write(&max_path, format!("{} {}={}", device, key, rate))
+                    .context(format!("Failed to set IO limit {} in {}", limit, max_path))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// --blkio-weight 与 cgroup v1 的 blkio.weight 一样取值 10 到 1000，按比例换算为 io.weight 的 1 到 10000
+fn io_weight(blkio_weight: u16) -> Result<u64> {
+    if !(10..=1000).contains(&blkio_weight) {
+        return Err(Error::InvalidArgument(format!("Invalid blkio weight {}, must be between 10 and 1000", blkio_weight)));
+    }
+    Ok(1 + (blkio_weight as u64 - 10) * 9999 / 990)
+}
+
+// --device-read-bps 形如 /dev/sda:10mb，返回 io.max 中使用的 major:minor 和每秒的字节数
+fn parse_device_rate(limit: &str) -> Result<(String, u64)> {
+    let Some((device, rate)) = limit.rsplit_once(':') else {
+        return Err(Error::InvalidArgument(format!("Invalid device rate {}, expected <device>:<rate>", limit)));
+    };
+    let stat = stat(device).context(format!("Failed to stat device {}", device))?;
+    if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT != SFlag::S_IFBLK {
+        return Err(Error::InvalidArgument(format!("{} is not a block device", device)));
+    }
+    // 与 docker 一样接受 1048576、1024k、1mb 等写法
+    let number = rate.trim_end_matches(['b', 'B']);
+    let rate = match number.parse::<u64>() {
+        Ok(bytes) => bytes,
+        Err(_) => parse_memory(number)
+            .map_err(|_| Error::InvalidArgument(format!("Invalid rate {}, expected a number followed by kb, mb or gb", rate)))?,
+    };
+    Ok((format!("{}:{}", major(stat.st_rdev), minor(stat.st_rdev)), rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_blkio_weight() {
+        assert_eq!(io_weight(10).unwrap(), 1);
+        assert_eq!(io_weight(500).unwrap(), 4950);
+        assert_eq!(io_weight(1000).unwrap(), 10000);
+        assert!(io_weight(9).is_err());
+        assert!(io_weight(1001).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_device_rates() {
+        // /dev/null 是字符设备，io.max 只能限制块设备
+        for limit in ["", "/dev/null", "/dev/null:1mb", "/nonexistent/device:1mb"] {
+            assert!(parse_device_rate(limit).is_err(), "{:?} should be rejected", limit);
+        }
+    }
+}
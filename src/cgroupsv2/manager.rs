@@ -1,77 +1,255 @@
-use std::fs::exists;
+use std::collections::BTreeMap;
+use std::fs::{exists, File, OpenOptions};
+use std::io::ErrorKind;
+use std::path::Path;
 
 use log::info;
 
 use super::cpu::CGroupCPU;
+use super::cpuset::CGroupCpuset;
+use super::hugetlb::CGroupHugetlb;
+use super::io::CGroupIO;
+use super::pids::CGroupPids;
+use super::devices::{CGroupDevices, DeviceRule};
 use super::memory::CGroupMemory;
+use crate::error::{Context, Error, Result};
+use crate::state::{cgroup_parent, is_rootless};
 
-pub const CGROUP_ROOTPATH: &str = "/sys/fs/cgroup/mydocker";
+const CGROUP_MOUNTPOINT: &str = "/sys/fs/cgroup";
+const CGROUP_ROOTPATH: &str = "/sys/fs/cgroup/mydocker";
+
+// inspect 时读取的 cgroup 接口文件，包括资源限制和当前的使用量
+const STAT_FILES: [&str; 12] = [
+    "cpu.max", "cpu.stat",
+    "memory.max", "memory.current", "memory.peak", "memory.events",
+    "pids.max", "pids.current",
+    "cpuset.cpus.effective", "cpuset.mems.effective", "io.max", "io.weight",
+];
+
+// 不管有没有要求限制都开启的控制器，开销很小
+// OOM 的检测依赖 memory.events，inspect 显示的内存和进程数的使用量也要有对应的控制器才能读到
+const ACCOUNTING_CONTROLLERS: [&str; 2] = ["memory", "pids"];
 
 pub trait CGroupIf {
-    fn set(&self, path: &String, resource_config: &ResourceConfig);
+    // 满足 resource_config 需要在父 cgroup 中开启的控制器，没有要求相应的限制时为 None
+    fn controller(&self, resource_config: &ResourceConfig) -> Option<&'static str>;
+    fn set(&self, path: &str, resource_config: &ResourceConfig) -> Result<()>;
 }
 
 pub struct ResourceConfig {
     pub cpu: Option<u32>,
     pub memory: Option<String>,
+    pub cpuset_cpus: Option<String>,
+    pub cpuset_mems: Option<String>,
+    pub device_read_bps: Vec<String>,   // 形如 /dev/sda:10mb
+    pub device_write_bps: Vec<String>,
+    pub blkio_weight: Option<u16>,
+    pub pids: Option<i64>,
+    pub hugetlb: Vec<String>,           // 形如 2MB:1g
+    pub devices: Option<Vec<DeviceRule>>,  // 容器可以访问的设备，为 None 时不限制
 }
 
 pub struct CGroupManager {
-    path: String,   // 以 container_id 作为子目录的名称
+    root: Option<String>,   // mydocker 的 cgroup 根目录，rootless 模式下没有可用的 cgroup 时为 None
+    path: String,           // 以 container_id 作为子目录的名称
     cgroups: Vec<Box<dyn CGroupIf>>,
 }
 
+// mydocker 的 cgroup 根目录：指定了 cgroup parent 时为 /sys/fs/cgroup 下的对应目录，否则 root 用户为 /sys/fs/cgroup/mydocker
+// rootless 模式下默认只能使用 systemd 委派给当前用户的 user@<uid>.service，在它下面创建 mydocker 子目录
+// mydocker 自身需要运行在 user@<uid>.service 之内（如通过 systemd-run --user --scope），才有权限把容器进程移入其中
+fn cgroup_root() -> Option<String> {
+    if let Some(parent) = cgroup_parent() {
+        return Some(format!("{}/{}", CGROUP_MOUNTPOINT, parent));
+    }
+    if !is_rootless() {
+        return Some(CGROUP_ROOTPATH.to_string());
+    }
+    // cgroup v2 下 /proc/self/cgroup 只有一行，形如 0::/user.slice/user-1000.slice/user@1000.service/app.slice/xxx.scope
+    let cgroup = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    let path = cgroup.lines().find_map(|line| line.strip_prefix("0::"))?;
+    let service = format!("user@{}.service", nix::unistd::geteuid());
+    let end = path.find(&service)? + service.len();
+    Some(format!("{}{}/mydocker", CGROUP_MOUNTPOINT, &path[..end]))
+}
+
 impl CGroupManager {
     pub fn new(path: String) -> Self {
-        if !exists(CGROUP_ROOTPATH).unwrap() {
-            std::fs::create_dir_all(CGROUP_ROOTPATH).expect("Failed to create cgroup root path");
-            std::fs::write(CGROUP_ROOTPATH.to_string() + "/cgroup.subtree_control", "+cpu +memory").expect("Failed to write cgroup.subtree_control");
-        }
-
         CGroupManager {
+            root: cgroup_root(),
             path,
             cgroups: vec![
                 Box::new(CGroupCPU::new()),
                 Box::new(CGroupMemory::new()),
+                Box::new(CGroupCpuset::new()),
+                Box::new(CGroupIO::new()),
+                Box::new(CGroupPids::new()),
+                Box::new(CGroupHugetlb::new()),
+                Box::new(CGroupDevices::new()),
             ],
         }
     }
 
-    pub fn create_cgroup(&self) {
+    // mydocker 的 cgroup 根目录在第一次创建容器时创建
+    fn init_root(root: &str) -> Result<()> {
+        if exists(root).context(format!("Failed to check {}", root))? {
+            return Ok(());
+        }
+        std::fs::create_dir_all(root).context(format!("Failed to create {}", root))
+    }
+
+    // 容器的 cgroup 中只有开启了的控制器才有对应的接口文件，开启统计用的控制器和要求的限制需要的控制器
+    // 已经开启的控制器再次开启不会出错，其他容器开启的控制器也不会被关闭
+    fn enable_controllers(root: &str, controllers: &[&str]) -> Result<()> {
+        // 从挂载点到 mydocker 的根目录的每一级，嵌套的 --cgroup-parent 中间的 cgroup 也要开启
+        let mut levels: Vec<&Path> = Path::new(root).ancestors()
+            .take_while(|level| level.starts_with(CGROUP_MOUNTPOINT))
+            .collect();
+        levels.reverse();
+        for controller in ACCOUNTING_CONTROLLERS.iter().chain(controllers) {
+            match Self::enable_controller(&levels, controller) {
+                Ok(()) => {}
+                // 统计用的控制器不可用时只是读不到使用量，要求了限制的控制器不可用则无法满足
+                Err(e) if !controllers.contains(controller) => info!("{}, its usage will not be reported", e),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // 子 cgroup 只能使用父 cgroup 在 cgroup.subtree_control 中开启了的控制器，所以要从上往下逐级开启
+    fn enable_controller(levels: &[&Path], controller: &str) -> Result<()> {
+        for level in levels {
+            // 挂载点下可能有不属于 cgroup2 的目录，如 cgroup v1 与 v2 混用时 /sys/fs/cgroup 本身是 tmpfs
+            let Ok(available) = std::fs::read_to_string(level.join("cgroup.controllers")) else {
+                continue;
+            };
+            if !available.split_whitespace().any(|c| c == controller) {
+                return Err(Error::Runtime(format!(
+                    "The {} controller is not available in {}, its parent cgroup does not delegate it", controller, level.display()
+                )));
+            }
+            let subtree_control = level.join("cgroup.subtree_control");
+            let enabled = std::fs::read_to_string(&subtree_control)
+                .context(format!("Failed to read {}", subtree_control.display()))?;
+            if enabled.split_whitespace().any(|c| c == controller) {
+                continue;
+            }
+            std::fs::write(&subtree_control, format!("+{}", controller))
+                .context(format!("Failed to enable the {} controller in {}", controller, level.display()))?;
+        }
+        Ok(())
+    }
+
+    pub fn controllers(&self, resource_config: &ResourceConfig) -> Vec<&'static str> {
+        self.cgroups.iter().filter_map(|cgroup| cgroup.controller(resource_config)).collect()
+    }
+
+    // rootless 模式下 systemd 没有委派 cgroup 时为 false，此时容器不使用 cgroup，也就不能限制资源
+    pub fn is_available(&self) -> bool {
+        self.root.is_some()
+    }
+
+    pub fn create_cgroup(&self) -> Result<()> {
+        let (root, cgroup_path) = self.require_path()?;
+        Self::init_root(root)?;
         // 创建 cgroup 目录
-        let cgroup_path = format!("{}/{}", CGROUP_ROOTPATH, self.path);
-        std::fs::create_dir_all(cgroup_path).expect("Failed to create cgroup directory");
+        std::fs::create_dir_all(&cgroup_path).context(format!("Failed to create cgroup {}", cgroup_path))
     }
 
-    pub fn destroy_cgroup(&self) {
+    pub fn destroy_cgroup(&self) -> Result<()> {
         // 删除 cgroup 目录
-        let cgroup_path = format!("{}/{}", CGROUP_ROOTPATH, self.path);
+        let Some(cgroup_path) = self.get_path() else {
+            return Ok(());
+        };
+        if !exists(&cgroup_path).unwrap_or(true) {
+            return Ok(());
+        }
         info!("Destroying cgroup: {}", cgroup_path);
         // use rmdir
-        std::process::Command::new("rmdir") // 使用 rmdir 命令才能删除 cgroup 目录，std::fs::remove_dir_all 无法删除 cgroup 目录
-            .arg(cgroup_path)
+        let output = std::process::Command::new("rmdir") // 使用 rmdir 命令才能删除 cgroup 目录，std::fs::remove_dir_all 无法删除 cgroup 目录
+            .arg(&cgroup_path)
             .output()
-            .expect("Failed to remove cgroup directory");
+            .context("Failed to execute rmdir command")?;
+        if !output.status.success() {
+            return Err(Error::Runtime(format!(
+                "Failed to remove cgroup {}: {}", cgroup_path, String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
     }
 
-    pub fn add_process(&self, pid: u32) {
+    pub fn add_process(&self, pid: u32) -> Result<()> {
         // 将进程添加到 cgroup
-        let cgroup_path = format!("{}/{}", CGROUP_ROOTPATH, self.path);
+        let (_, cgroup_path) = self.require_path()?;
         let pid_path = format!("{}/cgroup.procs", cgroup_path);
-        std::fs::write(pid_path, pid.to_string()).expect("Failed to add process to cgroup");
+        std::fs::write(pid_path, pid.to_string()).context(format!("Failed to add process {} to cgroup {}", pid, cgroup_path))
+    }
+
+    // 打开 cgroup.procs，之后写入 PID 即可把进程加入 cgroup；容器没有使用 cgroup 时返回 None
+    // exec 进入容器的 mount namespace 之后看不到宿主机上的 cgroup 目录，要提前打开
+    pub fn open_procs(&self) -> Result<Option<File>> {
+        let Some(cgroup_path) = self.get_path() else {
+            return Ok(None);
+        };
+        let procs_path = format!("{}/cgroup.procs", cgroup_path);
+        match OpenOptions::new().write(true).open(&procs_path) {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(format!("Failed to open {}", procs_path)),
+        }
     }
 
-    pub fn set(&self, resource_config: ResourceConfig) {
+    pub fn set(&self, resource_config: ResourceConfig) -> Result<()> {
+        let (root, cgroup_path) = self.require_path()?;
+        Self::enable_controllers(root, &self.controllers(&resource_config))?;
         for cgroup in &self.cgroups {
-            cgroup.set(&self.path, &resource_config);
+            cgroup.set(&cgroup_path, &resource_config)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_path(&self) -> Option<String> {
+        self.root.as_ref().map(|root| format!("{}/{}", root, self.path))
+    }
+
+    fn require_path(&self) -> Result<(&str, String)> {
+        match &self.root {
+            Some(root) => Ok((root, format!("{}/{}", root, self.path))),
+            None => Err(Error::Runtime("No delegated cgroup is available for rootless containers".to_string())),
         }
     }
 
-    pub fn check_cgroup_memory_events(&self) {
-        // 检查 cgroup 内存事件
-        let cgroup_path = format!("{}/{}", CGROUP_ROOTPATH, self.path);
-        let memory_events_path = format!("{}/memory.events.local", cgroup_path);
-        let memory_events = std::fs::read_to_string(memory_events_path).expect("Failed to read memory events");
+    // 读取 cgroup 当前的限制和使用量，以接口文件名为 key；容器已退出、cgroup 不存在时返回空表
+    pub fn stats(&self) -> BTreeMap<String, String> {
+        let Some(cgroup_path) = self.get_path() else {
+            return BTreeMap::new();
+        };
+        STAT_FILES.iter()
+            .filter_map(|file| {
+                let content = std::fs::read_to_string(format!("{}/{}", cgroup_path, file)).ok()?;
+                Some((file.to_string(), content.trim().to_string()))
+            })
+            .collect()
+    }
+
+    // 检查 cgroup 内存事件，返回 cgroup 中是否有进程被 OOM killer 杀死
+    pub fn check_cgroup_memory_events(&self) -> bool {
+        let Some(cgroup_path) = self.get_path() else {
+            return false;
+        };
+        let memory_events_path = format!("{}/memory.events", cgroup_path);
+        let memory_events = match std::fs::read_to_string(&memory_events_path) {
+            Ok(memory_events) => memory_events,
+            Err(e) => {
+                info!("Failed to read {}: {}", memory_events_path, e);
+                return false;
+            }
+        };
         info!("Memory events: {}", memory_events);
+        // 每行形如 "oom_kill 1"
+        memory_events.lines()
+            .filter_map(|line| line.split_once(' '))
+            .any(|(key, value)| key == "oom_kill" && value.trim().parse::<u64>().unwrap_or(0) > 0)
     }
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
-use super::manager::{CGroupIf, ResourceConfig, CGROUP_ROOTPATH};
+use super::manager::{CGroupIf, ResourceConfig};
+use crate::error::{Context, Error, Result};
 
 
 pub struct CGroupMemory {
@@ -12,24 +13,37 @@ impl CGroupMemory {
 }
 
 impl CGroupIf for CGroupMemory {
-    fn set(&self, path: &String, resource_config: &ResourceConfig) {
+    fn controller(&self, resource_config: &ResourceConfig) -> Option<&'static str> {
+        resource_config.memory.as_ref().map(|_| "memory")
+    }
+
+    fn set(&self, path: &str, resource_config: &ResourceConfig) -> Result<()> {
         let memory_limit = if let Some(memory) = &resource_config.memory {
             memory
         } else {
-            return;
-        };
-        let memory_path = format!("{}/{}/memory.max", CGROUP_ROOTPATH, path);
-        // memory_limit 是一个字符串，如 10G 10m 10M 20k 等
-        let memory = if memory_limit.ends_with(&['g', 'G']) {
-            memory_limit.trim_end_matches(&['g', 'G']).parse::<u64>().unwrap() * 1024 * 1024 * 1024
-        } else if memory_limit.ends_with(&['m', 'M']) {
-            memory_limit.trim_end_matches(&['m', 'M']).parse::<u64>().unwrap() * 1024 * 1024
-        } else if memory_limit.ends_with(&['k', 'K']) {
-            memory_limit.trim_end_matches(&['k', 'K']).parse::<u64>().unwrap() * 1024
-        } else {
-            panic!("Invalid memory limit format");
+            return Ok(());
         };
-        let memory_limit_str = format!("{}", memory);
-        std::fs::write(&memory_path, memory_limit_str).expect("Failed to set memory limit");
+        let memory_path = format!("{}/memory.max", path);
+        let memory_limit_str = format!("{}", parse_memory(memory_limit)?);
+        std::fs::write(&memory_path, memory_limit_str).context(format!("Failed to set memory limit in {}", memory_path))
     }
-}
\ No newline at end of file
+}
+
+// memory_limit 是一个字符串，如 10G 10m 10M 20k 等
+pub fn parse_memory(memory_limit: &str) -> Result<u64> {
+    let (number, unit) = if memory_limit.ends_with(['g', 'G']) {
+        (memory_limit.trim_end_matches(['g', 'G']), 1024 * 1024 * 1024)
+    } else if memory_limit.ends_with(['m', 'M']) {
+        (memory_limit.trim_end_matches(['m', 'M']), 1024 * 1024)
+    } else if memory_limit.ends_with(['k', 'K']) {
+        (memory_limit.trim_end_matches(['k', 'K']), 1024)
+    } else {
+        (memory_limit, 0)
+    };
+    match number.parse::<u64>() {
+        Ok(number) if unit > 0 => Ok(number * unit),
+        _ => Err(Error::InvalidArgument(
+            format!("Invalid memory limit {}, expected a number followed by k, m or g", memory_limit)
+        )),
+    }
+}
@@ -1,5 +1,11 @@
 mod cpu;
 mod memory;
+mod devices;
+mod cpuset;
+mod io;
+mod pids;
+mod hugetlb;
 pub mod manager;
 
-pub use manager::{CGroupManager, ResourceConfig};
\ No newline at end of file
+pub use manager::{CGroupManager, ResourceConfig};
+pub use devices::DeviceRule;
\ No newline at end of file
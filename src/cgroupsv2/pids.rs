@@ -0,0 +1,28 @@
+use super::manager::{CGroupIf, ResourceConfig};
+use crate::error::{Context, Result};
+
+pub struct CGroupPids {
+
+}
+
+impl CGroupPids {
+    pub fn new() -> Self {
+        CGroupPids {}
+    }
+}
+
+impl CGroupIf for CGroupPids {
+    fn controller(&self, resource_config: &ResourceConfig) -> Option<&'static str> {
+        resource_config.pids.map(|_| "pids")
+    }
+
+    fn set(&self, path: &str, resource_config: &ResourceConfig) -> Result<()> {
+        let Some(pids) = resource_config.pids else {
+            return Ok(());
+        };
+        // 与 docker 一样，0 和负数表示不限制
+        let pids_limit = if pids > 0 { pids.to_string() } else { "max".to_string() };
+        let pids_path = format!("{}/pids.max", path);
+        std::fs::write(&pids_path, pids_limit).context(format!("Failed to set pids limit in {}", pids_path))
+    }
+}
@@ -1,12 +1,27 @@
 use std::process::Command;
 
-use crate::run::{ROOTFS_BASE_PATH, IMAGE_BASE_PATH};
+use crate::state::{image_base_path, rootfs_base_path};
+use crate::container::{container_env, get_command, load_image_config, resolve_container_id, save_image_config, ImageConfig};
+use crate::error::{Context, Error, Result};
 
-pub fn commit_container(container_id: &str, image: &str) {
-    let rootfs = ROOTFS_BASE_PATH.to_string() + container_id + "/merged";
-    let target = IMAGE_BASE_PATH.to_string() + image + ".tar";
-    Command::new("tar")
-        .args(&["-cf", &target, &rootfs])
+pub fn commit_container(name_or_id: &str, image: &str) -> Result<()> {
+    let container_id = resolve_container_id(name_or_id)?;
+    let rootfs = rootfs_base_path() + &container_id + "/merged";
+    let target = image_base_path() + image + ".tar";
+    let status = Command::new("tar")
+        .args(["-cf", &target, &rootfs])
         .status()
-        .expect("failed to execute tar command");
+        .context("Failed to execute tar command")?;
+    if !status.success() {
+        return Err(Error::Runtime(format!("Failed to commit container {} to {}: tar {}", container_id, target, status)));
+    }
+
+    // 新镜像沿用容器的环境变量和工作目录，HOSTNAME 和 TERM 由创建容器时重新生成
+    let run_command = get_command(&container_id)?;
+    let base = load_image_config(&run_command.image)?;
+    let env = container_env(&container_id, &run_command, &base).into_iter()
+        .filter(|entry| !entry.starts_with("HOSTNAME=") && !entry.starts_with("TERM="))
+        .collect();
+    let workdir = run_command.workdir.or(base.workdir);
+    save_image_config(image, &ImageConfig { env, workdir })
 }
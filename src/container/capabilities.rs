@@ -0,0 +1,132 @@
+use libc::{c_int, syscall, SYS_capget, SYS_capset};
+use nix::errno::Errno;
+
+use crate::RunCommand;
+use crate::error::{Context, Error, Result};
+
+// 按编号排列的 capability 名称，下标即 capability 的值
+const CAPABILITIES: [&str; 41] = [
+    "CAP_CHOWN", "CAP_DAC_OVERRIDE", "CAP_DAC_READ_SEARCH", "CAP_FOWNER", "CAP_FSETID", "CAP_KILL",
+    "CAP_SETGID", "CAP_SETUID", "CAP_SETPCAP", "CAP_LINUX_IMMUTABLE", "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST", "CAP_NET_ADMIN", "CAP_NET_RAW", "CAP_IPC_LOCK", "CAP_IPC_OWNER", "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO", "CAP_SYS_CHROOT", "CAP_SYS_PTRACE", "CAP_SYS_PACCT", "CAP_SYS_ADMIN", "CAP_SYS_BOOT",
+    "CAP_SYS_NICE", "CAP_SYS_RESOURCE", "CAP_SYS_TIME", "CAP_SYS_TTY_CONFIG", "CAP_MKNOD", "CAP_LEASE",
+    "CAP_AUDIT_WRITE", "CAP_AUDIT_CONTROL", "CAP_SETFCAP", "CAP_MAC_OVERRIDE", "CAP_MAC_ADMIN", "CAP_SYSLOG",
+    "CAP_WAKE_ALARM", "CAP_BLOCK_SUSPEND", "CAP_AUDIT_READ", "CAP_PERFMON", "CAP_BPF", "CAP_CHECKPOINT_RESTORE",
+];
+
+// 与 docker 默认保留的 capability 相同
+const DEFAULT_CAPABILITIES: [&str; 14] = [
+    "CAP_CHOWN", "CAP_DAC_OVERRIDE", "CAP_FSETID", "CAP_FOWNER", "CAP_MKNOD", "CAP_NET_RAW", "CAP_SETGID",
+    "CAP_SETUID", "CAP_SETFCAP", "CAP_SETPCAP", "CAP_NET_BIND_SERVICE", "CAP_SYS_CHROOT", "CAP_KILL",
+    "CAP_AUDIT_WRITE",
+];
+
+const ALL: &str = "ALL";
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: c_int,
+}
+
+// 64 位的集合分为低 32 位和高 32 位两项
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+// --cap-add、--cap-drop 中的名称不区分大小写，可以省略 CAP_ 前缀，ALL 表示全部
+fn normalize(name: &str) -> Result<String> {
+    let upper = name.to_ascii_uppercase();
+    if upper == ALL {
+        return Ok(upper);
+    }
+    let name = if upper.starts_with("CAP_") { upper } else { format!("CAP_{}", upper) };
+    if !CAPABILITIES.contains(&name.as_str()) {
+        return Err(Error::InvalidArgument(format!("Unknown capability {}", name)));
+    }
+    Ok(name)
+}
+
+// 容器进程保留的 capability：--privileged 时为全部，否则为 docker 的默认集合加上 --cap-add、去掉 --cap-drop
+// 返回的名称按编号排序，记录在元信息中供 exec 和 inspect 使用
+pub fn container_capabilities(command: &RunCommand) -> Result<Vec<String>> {
+    let add = command.cap_add.iter().map(|name| normalize(name)).collect::<Result<Vec<_>>>()?;
+    let drop = command.cap_drop.iter().map(|name| normalize(name)).collect::<Result<Vec<_>>>()?;
+    let keep = |name: &str| {
+        if command.privileged {
+            return true;
+        }
+        if drop.iter().any(|d| d == name) {
+            return false;
+        }
+        if add.iter().any(|a| a == name || a == ALL) {
+            return true;
+        }
+        !drop.iter().any(|d| d == ALL) && DEFAULT_CAPABILITIES.contains(&name)
+    };
+    Ok(CAPABILITIES.iter().filter(|name| keep(name)).map(|name| name.to_string()).collect())
+}
+
+pub fn capability_mask(names: &[String]) -> u64 {
+    CAPABILITIES.iter().enumerate()
+        .filter(|(_, name)| names.iter().any(|n| n == *name))
+        .fold(0, |mask, (cap, _)| mask | 1 << cap)
+}
+
+// 从 bounding set 中去掉不保留的 capability，之后 execvp 的程序无论如何都无法再获得它们
+// 需要 CAP_SETPCAP，所以要在切换用户之前调用
+pub fn drop_bounding_set(mask: u64) -> Result<()> {
+    for cap in 0..=last_cap() {
+        if mask & (1 << cap) != 0 {
+            continue;
+        }
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) } < 0 {
+            return Err(Errno::last()).context(format!("Failed to drop {} from bounding set", cap_name(cap)));
+        }
+    }
+    Ok(())
+}
+
+// 把 effective、permitted、inheritable 限制为 mask，并清空 ambient set
+// 切换到非 root 用户后 permitted 已经被内核清空，这里只会进一步缩小，不会重新获得 capability
+pub fn limit_capabilities(mask: u64) -> Result<()> {
+    let mut header = CapHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+    let mut data = [CapData::default(); 2];
+    if unsafe { syscall(SYS_capget, &mut header, data.as_mut_ptr()) } < 0 {
+        return Err(Errno::last()).context("capget failed");
+    }
+    for (i, item) in data.iter_mut().enumerate() {
+        let mask = (mask >> (32 * i)) as u32;
+        item.effective &= mask;
+        item.permitted &= mask;
+        item.inheritable &= mask;
+    }
+    if unsafe { syscall(SYS_capset, &mut header, data.as_ptr()) } < 0 {
+        return Err(Errno::last()).context("capset failed");
+    }
+    let clear_ambient = unsafe {
+        libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong, 0, 0, 0)
+    };
+    // 3.x 内核没有 ambient set
+    if clear_ambient < 0 && Errno::last() != Errno::EINVAL {
+        return Err(Errno::last()).context("Failed to clear ambient capabilities");
+    }
+    Ok(())
+}
+
+// 当前内核支持的最大 capability 编号，比 CAPABILITIES 新的 capability 同样会被去掉
+fn last_cap() -> usize {
+    std::fs::read_to_string("/proc/sys/kernel/cap_last_cap").ok()
+        .and_then(|last| last.trim().parse().ok())
+        .unwrap_or(CAPABILITIES.len() - 1)
+}
+
+fn cap_name(cap: usize) -> String {
+    CAPABILITIES.get(cap).map_or_else(|| cap.to_string(), |name| name.to_string())
+}
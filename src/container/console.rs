@@ -0,0 +1,291 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::Duration;
+
+use log::{error, info, warn};
+use nix::errno::Errno;
+use nix::fcntl::{open, OFlag};
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::pty::Winsize;
+use nix::sys::stat::Mode;
+use nix::unistd::{dup2, pipe2, setsid};
+
+use crate::container::{open_pty, set_winsize, setup_controlling_terminal};
+use crate::state::metainfo_base_path;
+
+// attach 客户端发给 console server 的帧：1 字节类型 + 2 字节负载长度（大端）+ 负载
+// console server 发给客户端的则是容器原始的输出流，不做封装
+pub const FRAME_STDIN: u8 = 0;
+pub const FRAME_RESIZE: u8 = 1;     // 负载为大端的 rows、cols 两个 u16
+
+// 每个客户端最多缓存的尚未发出的输出，超过时断开这个客户端，不让它拖慢容器和其他客户端
+const MAX_CLIENT_BUFFER: usize = 1024 * 1024;
+
+pub fn console_socket_path(container_id: &str) -> String {
+    format!("{}{}/attach.sock", metainfo_base_path(), container_id)
+}
+
+pub fn encode_frame(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 3);
+    frame.push(kind);
+    frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// 容器 init 进程一侧使用的标准输入输出
+#[derive(Clone, Copy)]
+pub enum ContainerStdio {
+    Pty(RawFd),                                     // pty slave，同时作为容器的控制终端
+    Pipes { stdin: Option<RawFd>, output: RawFd },  // 没有 -i 时 stdin 为 /dev/null，stdout 和 stderr 共用一个管道
+}
+
+// 宿主机一侧的 fd，由 console server 持有
+pub struct ConsoleIo {
+    input: Option<OwnedFd>,
+    output: OwnedFd,
+    tty: bool,
+}
+
+// 后台运行的容器的标准输入输出
+// 宿主机一侧的 fd 都设置了 O_CLOEXEC，容器进程 execvp 之后只剩下 dup2 到 0、1、2 的那一份
+pub struct Console {
+    host: ConsoleIo,
+    container: Vec<OwnedFd>,
+    stdio: ContainerStdio,
+}
+
+impl Console {
+    pub fn new(tty: bool, interactive: bool) -> std::io::Result<Self> {
+        if tty {
+            let (master, slave) = open_pty()?;
+            let input = if interactive { Some(master.try_clone()?) } else { None };
+            let stdio = ContainerStdio::Pty(slave.as_raw_fd());
+            return Ok(Console {
+                host: ConsoleIo { input, output: master, tty },
+                container: vec![slave],
+                stdio,
+            });
+        }
+
+        let (output_read, output_write) = pipe2(OFlag::O_CLOEXEC)?;
+        let mut stdio_in = None;
+        let mut input = None;
+        let mut container = vec![];
+        if interactive {
+            let (stdin_read, stdin_write) = pipe2(OFlag::O_CLOEXEC)?;
+            stdio_in = Some(stdin_read.as_raw_fd());
+            input = Some(stdin_write);
+            container.push(stdin_read);
+        }
+        let stdio = ContainerStdio::Pipes { stdin: stdio_in, output: output_write.as_raw_fd() };
+        container.push(output_write);
+        Ok(Console {
+            host: ConsoleIo { input, output: output_read, tty },
+            container,
+            stdio,
+        })
+    }
+
+    pub fn stdio(&self) -> ContainerStdio {
+        self.stdio
+    }
+
+    // clone 之后调用，关闭父进程中容器一侧的 fd，只保留交给 console server 的部分
+    pub fn into_host(self) -> ConsoleIo {
+        drop(self.container);
+        self.host
+    }
+}
+
+// 在容器的 init 进程中调用，把标准输入输出切换到 mydocker 准备好的 pty 或管道上
+pub fn setup_stdio(stdio: ContainerStdio) -> nix::Result<()> {
+    match stdio {
+        ContainerStdio::Pty(slave) => setup_controlling_terminal(slave),
+        ContainerStdio::Pipes { stdin, output } => {
+            // 脱离 mydocker 所在的会话，避免收到用户终端的信号
+            setsid()?;
+            let stdin = match stdin {
+                Some(fd) => fd,
+                None => open("/dev/null", OFlag::O_RDONLY, Mode::empty())?,
+            };
+            dup2(stdin, 0)?;
+            dup2(output, 1)?;
+            dup2(output, 2)?;
+            Ok(())
+        }
+    }
+}
+
+struct Client {
+    stream: UnixStream,
+    pending: Vec<u8>,   // 尚未凑成完整帧的数据
+    outgoing: Vec<u8>,  // 尚未发给客户端的输出
+}
+
+impl Client {
+    // 客户端的 socket 是非阻塞的，写到 socket 的缓冲区满为止，剩下的等 POLLOUT 之后再写
+    // 返回 false 表示客户端已经断开
+    fn flush(&mut self) -> bool {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+}
+
+// 由 shim 调用：将容器的输出追加到 container.log 并转发给所有 attach 上来的客户端，客户端的输入写回容器
+// 容器一侧的输出关闭（所有进程都已退出）后返回
+// 所有 fd 在同一个循环中处理，客户端的 socket 都是非阻塞的，读不及时的客户端不会阻塞容器的输出
+pub fn serve_console(container_id: &str, io: ConsoleIo) {
+    let socket_path = console_socket_path(container_id);
+    let _ = std::fs::remove_file(&socket_path);
+    let listener = match UnixListener::bind(&socket_path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind console socket {}: {}", socket_path, e);
+            return;
+        }
+    };
+
+    let log_path = format!("{}{}/container.log", metainfo_base_path(), container_id);
+    let mut log_file = match OpenOptions::new().append(true).create(true).open(&log_path) {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Failed to open log file {}: {}", log_path, e);
+            return;
+        }
+    };
+
+    let tty = io.tty;
+    let mut output = File::from(io.output);
+    let mut input = io.input.map(File::from);
+    let mut clients: Vec<Client> = Vec::new();
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let mut fds = vec![
+            PollFd::new(output.as_fd(), PollFlags::POLLIN),
+            PollFd::new(listener.as_fd(), PollFlags::POLLIN),
+        ];
+        for client in &clients {
+            let events = if client.outgoing.is_empty() { PollFlags::POLLIN } else { PollFlags::POLLIN | PollFlags::POLLOUT };
+            fds.push(PollFd::new(client.stream.as_fd(), events));
+        }
+        match poll(&mut fds, PollTimeout::NONE) {
+            Ok(_) => {},
+            Err(Errno::EINTR) => continue,
+            Err(e) => {
+                error!("poll failed: {}", e);
+                break;
+            }
+        }
+        let ready = PollFlags::POLLIN | PollFlags::POLLHUP | PollFlags::POLLERR;
+        let revents: Vec<PollFlags> = fds.iter()
+            .map(|fd| fd.revents().unwrap_or(PollFlags::empty()))
+            .collect();
+        drop(fds);
+
+        // 先处理客户端的输入输出，再处理新连接，保证 revents 与 clients 的下标一致
+        let mut closed = vec![];
+        for (i, client) in clients.iter_mut().enumerate() {
+            let revents = revents[i + 2];
+            if revents.contains(PollFlags::POLLOUT) && !client.flush() {
+                closed.push(i);
+                continue;
+            }
+            if !revents.intersects(ready) {
+                continue;
+            }
+            match client.stream.read(&mut buf) {
+                Ok(n) if n > 0 => {
+                    client.pending.extend_from_slice(&buf[..n]);
+                    handle_frames(&mut client.pending, &mut input, output.as_fd(), tty);
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {}
+                _ => closed.push(i),
+            }
+        }
+        for i in closed.into_iter().rev() {
+            clients.remove(i);
+            info!("Client detached from container {}", container_id);
+        }
+
+        if revents[1].intersects(ready) {
+            match listener.accept().and_then(|(stream, _)| stream.set_nonblocking(true).map(|_| stream)) {
+                Ok(stream) => {
+                    info!("Client attached to container {}", container_id);
+                    clients.push(Client { stream, pending: vec![], outgoing: vec![] });
+                }
+                Err(e) => error!("Failed to accept console client: {}", e),
+            }
+        }
+
+        if revents[0].intersects(ready) {
+            // 使用 pty 时，容器内所有持有 slave 的进程退出后读 master 会返回 EIO
+            match output.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let _ = log_file.write_all(&buf[..n]);
+                    clients.retain_mut(|client| {
+                        if client.outgoing.len() + n > MAX_CLIENT_BUFFER {
+                            warn!("Client of container {} is not reading its output, detaching it", container_id);
+                            return false;
+                        }
+                        client.outgoing.extend_from_slice(&buf[..n]);
+                        client.flush()
+                    });
+                }
+            }
+        }
+    }
+
+    // 把剩下的输出发给还在读的客户端，最多等待一秒
+    for client in &mut clients {
+        let _ = client.stream.set_nonblocking(false)
+            .and_then(|_| client.stream.set_write_timeout(Some(Duration::from_secs(1))))
+            .and_then(|_| client.stream.write_all(&client.outgoing));
+    }
+    info!("Container {} closed its output, console server exits", container_id);
+    let _ = std::fs::remove_file(&socket_path);
+}
+
+fn handle_frames(pending: &mut Vec<u8>, input: &mut Option<File>, master: BorrowedFd, tty: bool) {
+    while pending.len() >= 3 {
+        let len = u16::from_be_bytes([pending[1], pending[2]]) as usize;
+        if pending.len() < len + 3 {
+            break;
+        }
+        let payload = &pending[3..len + 3];
+        match pending[0] {
+            FRAME_STDIN => {
+                if let Some(file) = input
+                    && file.write_all(payload).is_err()
+                {
+                    *input = None;
+                }
+            }
+            FRAME_RESIZE if tty && payload.len() == 4 => {
+                let ws = Winsize {
+                    ws_row: u16::from_be_bytes([payload[0], payload[1]]),
+                    ws_col: u16::from_be_bytes([payload[2], payload[3]]),
+                    ws_xpixel: 0,
+                    ws_ypixel: 0,
+                };
+                set_winsize(master, &ws);
+            }
+            _ => {}
+        }
+        pending.drain(..len + 3);
+    }
+}
@@ -0,0 +1,214 @@
+use nix::mount::{mount, MsFlags};
+use nix::sys::stat::{major, makedev, minor, mknod, stat, Mode, SFlag};
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+
+use crate::RunCommand;
+use crate::cgroupsv2::DeviceRule;
+use crate::container::ContainerStdio;
+use crate::error::{Context, Error, Result};
+
+// 容器中只有这些设备，与 docker 相同；/dev/ptmx 是指向 /dev/pts/ptmx 的符号链接
+const DEFAULT_DEVICES: [(&str, u64, u64); 6] = [
+    ("/dev/null", 1, 3),
+    ("/dev/zero", 1, 5),
+    ("/dev/full", 1, 7),
+    ("/dev/random", 1, 8),
+    ("/dev/urandom", 1, 9),
+    ("/dev/tty", 5, 0),
+];
+
+// 符号链接的目标和在 /dev 中的名字
+const DEV_SYMLINKS: [(&str, &str); 6] = [
+    ("/proc/self/fd", "fd"),
+    ("/proc/self/fd/0", "stdin"),
+    ("/proc/self/fd/1", "stdout"),
+    ("/proc/self/fd/2", "stderr"),
+    ("pts/ptmx", "ptmx"),
+    ("/proc/kcore", "core"),
+];
+
+const ALL_PERMISSIONS: &str = "rwm";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DeviceKind {
+    Char,
+    Block,
+}
+
+// 容器中的一个设备节点，host_path 为宿主机上对应的设备
+pub struct Device {
+    pub host_path: String,
+    pub path: String,
+    pub kind: DeviceKind,
+    pub major: u64,
+    pub minor: u64,
+    pub mode: u32,              // 设备文件的权限位
+    pub permissions: String,    // --device 中的 rwm，r 读、w 写、m mknod，由 cgroup 的设备控制器限制
+}
+
+// 默认的设备加上 --device 指定的设备，容器中的路径相同时 --device 覆盖默认的设备
+pub fn container_devices(command: &RunCommand) -> Result<Vec<Device>> {
+    let mut devices: Vec<Device> = DEFAULT_DEVICES.iter()
+        .map(|&(path, major, minor)| Device {
+            host_path: path.to_string(),
+            path: path.to_string(),
+            kind: DeviceKind::Char,
+            major,
+            minor,
+            mode: 0o666,
+            permissions: ALL_PERMISSIONS.to_string(),
+        })
+        .collect();
+    for spec in &command.device {
+        let device = parse_device(spec)?;
+        devices.retain(|existing| existing.path != device.path);
+        devices.push(device);
+    }
+    Ok(devices)
+}
+
+// --device 形如 /dev/host[:/dev/container[:rwm]]，容器中的路径默认与宿主机相同
+fn parse_device(spec: &str) -> Result<Device> {
+    let mut parts = spec.splitn(3, ':');
+    let host_path = parts.next().unwrap_or_default();
+    let (path, permissions) = match (parts.next(), parts.next()) {
+        // 只有两段时第二段可能是权限
+        (Some(second), None) if !second.starts_with('/') => (host_path, second),
+        (Some(path), permissions) => (path, permissions.unwrap_or(ALL_PERMISSIONS)),
+        (None, _) => (host_path, ALL_PERMISSIONS),
+    };
+    if !path.starts_with('/') || path.split('/').any(|part| part == "..") {
+        return Err(Error::InvalidArgument(format!("Invalid device path {}, must be an absolute path", path)));
+    }
+    if permissions.is_empty() || !permissions.chars().all(|c| ALL_PERMISSIONS.contains(c)) {
+        return Err(Error::InvalidArgument(format!("Invalid device permissions {}, expected a combination of rwm", permissions)));
+    }
+
+    let stat = stat(host_path).context(format!("Failed to stat device {}", host_path))?;
+    let kind = match SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT {
+        SFlag::S_IFCHR => DeviceKind::Char,
+        SFlag::S_IFBLK => DeviceKind::Block,
+        _ => return Err(Error::InvalidArgument(format!("{} is not a device", host_path))),
+    };
+    Ok(Device {
+        host_path: host_path.to_string(),
+        path: path.to_string(),
+        kind,
+        major: major(stat.st_rdev),
+        minor: minor(stat.st_rdev),
+        mode: stat.st_mode & 0o777,
+        permissions: permissions.to_string(),
+    })
+}
+
+// 容器的 cgroup 允许访问的设备：默认的设备、--device 指定的设备和 --device-cgroup-rule，--privileged 时不限制
+pub fn device_rules(command: &RunCommand) -> Result<Option<Vec<DeviceRule>>> {
+    if command.privileged {
+        return Ok(None);
+    }
+    let mut rules = DeviceRule::default_rules();
+    for device in container_devices(command)? {
+        let rule = DeviceRule {
+            kind: Some(if device.kind == DeviceKind::Block { 'b' } else { 'c' }),
+            major: Some(device.major as u32),
+            minor: Some(device.minor as u32),
+            access: device.permissions,
+        };
+        if !rules.contains(&rule) {
+            rules.push(rule);
+        }
+    }
+    for rule in &command.device_cgroup_rule {
+        rules.push(DeviceRule::parse(rule)?);
+    }
+    Ok(Some(rules))
+}
+
+pub fn validate_devices(command: &RunCommand) -> Result<()> {
+    device_rules(command).map(|_| ())
+}
+
+// 在 pivot_root 之前、/dev 挂载了 tmpfs 之后调用，创建设备节点、符号链接和 /dev/console
+pub fn setup_devices(new_root: &Path, devices: &[Device], stdio: Option<ContainerStdio>, userns: bool) -> Result<()> {
+    for device in devices {
+        create_device(new_root, device, userns)?;
+    }
+    let dev = new_root.join("dev");
+    for (target, name) in DEV_SYMLINKS {
+        let link = dev.join(name);
+        symlink(target, &link).context(format!("Failed to create symlink {}", link.display()))?;
+    }
+    // 分配了终端时与 docker 一样把容器的 pty 绑定挂载为 /dev/console
+    // setup_stdio 已经把 pty slave 复制到标准输入输出并关闭了原来的 fd
+    // fd 所在的挂载属于宿主机的 mount namespace，不能直接绑定挂载，要通过路径找到当前 namespace 中的挂载
+    if let Some(ContainerStdio::Pty(_)) = stdio {
+        let console = dev.join("console");
+        let slave = std::fs::read_link("/proc/self/fd/0").context("Failed to resolve the container's pty")?;
+        std::fs::File::create(&console).context(format!("Failed to create {}", console.display()))?;
+        mount(Some(&slave), &console, None::<&Path>, MsFlags::MS_BIND, None::<&str>)
+            .context("Failed to bind mount /dev/console")?;
+    }
+    Ok(())
+}
+
+// user namespace 中不能 mknod，改为把宿主机上的设备绑定挂载到一个空文件上
+fn create_device(new_root: &Path, device: &Device, userns: bool) -> Result<()> {
+    let target = new_root.join(device.path.trim_start_matches('/'));
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent).context(format!("Failed to create {}", parent.display()))?;
+    }
+    if userns {
+        std::fs::File::create(&target).context(format!("Failed to create {}", target.display()))?;
+        return mount(Some(device.host_path.as_str()), &target, None::<&str>, MsFlags::MS_BIND, None::<&str>)
+            .context(format!("Failed to bind mount device {}", device.host_path));
+    }
+
+    let kind = match device.kind {
+        DeviceKind::Char => SFlag::S_IFCHR,
+        DeviceKind::Block => SFlag::S_IFBLK,
+    };
+    mknod(&target, kind, Mode::from_bits_truncate(device.mode), makedev(device.major, device.minor))
+        .context(format!("Failed to create device {}", device.path))?;
+    // mknod 的权限受 umask 影响
+    std::fs::set_permissions(&target, std::fs::Permissions::from_mode(device.mode))
+        .context(format!("Failed to set permissions of {}", device.path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(spec: &str) -> (String, String, String) {
+        let device = parse_device(spec).unwrap();
+        (device.host_path, device.path, device.permissions)
+    }
+
+    #[test]
+    fn parses_path_and_permissions() {
+        let strings = |parts: [&str; 3]| parts.map(str::to_string).into();
+        assert_eq!(parsed("/dev/null"), strings(["/dev/null", "/dev/null", "rwm"]));
+        assert_eq!(parsed("/dev/null:/dev/empty"), strings(["/dev/null", "/dev/empty", "rwm"]));
+        // 只有两段时第二段不以 / 开头就是权限
+        assert_eq!(parsed("/dev/null:r"), strings(["/dev/null", "/dev/null", "r"]));
+        assert_eq!(parsed("/dev/null:/dev/empty:rw"), strings(["/dev/null", "/dev/empty", "rw"]));
+    }
+
+    #[test]
+    fn reads_device_numbers_from_host() {
+        let device = parse_device("/dev/null").unwrap();
+        assert!(device.kind == DeviceKind::Char);
+        assert_eq!((device.major, device.minor), (1, 3));
+        assert_eq!(device.mode, 0o666);
+    }
+
+    #[test]
+    fn rejects_invalid_devices() {
+        for spec in [
+            "", "dev/null", "/dev/null:dev/null:rwm", "/dev/null:/dev/../etc/x", "/dev/null:/dev/x:", "/dev/null:rwx",
+            "/dev/null:/dev/x:rw:m", "/nonexistent/device", "/etc/passwd", "/dev",
+        ] {
+            assert!(parse_device(spec).is_err(), "{:?} should be rejected", spec);
+        }
+    }
+}
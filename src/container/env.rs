@@ -0,0 +1,158 @@
+use crate::RunCommand;
+use crate::container::{container_hostname, ImageConfig};
+use crate::error::{Context, Error, Result};
+
+// 与 docker 一样的默认 PATH，容器不继承宿主机的环境变量
+pub const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+// 把 --env-file 和 -e 合并为 KEY=VALUE 的列表，后出现的覆盖先出现的
+// 只写了 KEY 时取 mydocker 自身环境中的值，没有这个环境变量时忽略
+pub fn resolve_env(env: &[String], env_files: &[String]) -> Result<Vec<String>> {
+    let mut entries = vec![];
+    for env_file in env_files {
+        let content = std::fs::read_to_string(env_file).context(format!("Failed to read env file {}", env_file))?;
+        // 忽略空行和 # 开头的注释
+        entries.extend(content.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string));
+    }
+    entries.extend(env.iter().cloned());
+
+    let mut resolved = vec![];
+    for entry in entries {
+        let entry = match entry.split_once('=') {
+            Some(_) => entry,
+            None => match std::env::var(&entry) {
+                Ok(value) => format!("{}={}", entry, value),
+                Err(_) => continue,
+            },
+        };
+        validate_env(&entry)?;
+        resolved.push(entry);
+    }
+    Ok(merge_env(vec![], &resolved))
+}
+
+fn validate_env(entry: &str) -> Result<()> {
+    match entry.split_once('=') {
+        Some((key, _)) if !key.is_empty() && !entry.contains('\0') => Ok(()),
+        _ => Err(Error::InvalidArgument(format!("Invalid environment variable {:?}", entry))),
+    }
+}
+
+// 把 overrides 合并进 base，同名的变量保留原来的位置、使用新的值
+pub fn merge_env(mut base: Vec<String>, overrides: &[String]) -> Vec<String> {
+    for entry in overrides {
+        let key = entry.split('=').next().unwrap_or(entry);
+        match base.iter_mut().find(|existing| existing.split('=').next() == Some(key)) {
+            Some(existing) => existing.clone_from(entry),
+            None => base.push(entry.clone()),
+        }
+    }
+    base
+}
+
+// 容器进程的环境变量：默认的 PATH、HOSTNAME 和 TERM，然后依次是镜像中的默认值和 run 指定的值
+pub fn container_env(container_id: &str, command: &RunCommand, image: &ImageConfig) -> Vec<String> {
+    let mut env = vec![
+        format!("PATH={}", DEFAULT_PATH),
+        format!("HOSTNAME={}", container_hostname(container_id, command)),
+    ];
+    // 与 docker 一样只在分配了终端时设置 TERM
+    if command.tty {
+        env.push("TERM=xterm".to_string());
+    }
+    merge_env(merge_env(env, &image.env), &command.env)
+}
+
+// 容器进程的工作目录：-w 优先于镜像中的默认值，都没有时为 /
+pub fn container_workdir(workdir: Option<&str>, image: &ImageConfig) -> Result<String> {
+    let workdir = workdir.or(image.workdir.as_deref()).unwrap_or("/");
+    validate_workdir(workdir)?;
+    Ok(workdir.to_string())
+}
+
+pub fn validate_workdir(workdir: &str) -> Result<()> {
+    if !workdir.starts_with('/') || workdir.contains('\0') {
+        return Err(Error::InvalidArgument(format!("Invalid working directory {}, must be an absolute path", workdir)));
+    }
+    Ok(())
+}
+
+// 与 execvp 一样在 PATH 中查找没有 / 的命令，但使用的是容器的 PATH 而不是 mydocker 自身的
+// 没有设置 PATH 时使用默认值，PATH 中空的一项表示当前目录
+pub fn command_paths(command: &str, env: &[String]) -> Vec<String> {
+    if command.is_empty() {
+        return vec![];
+    }
+    if command.contains('/') {
+        return vec![command.to_string()];
+    }
+    let path = env.iter().rev()
+        .find_map(|entry| entry.strip_prefix("PATH="))
+        .unwrap_or(DEFAULT_PATH);
+    path.split(':')
+        .map(|dir| if dir.is_empty() { command.to_string() } else { format!("{}/{}", dir.trim_end_matches('/'), command) })
+        .collect()
+}
+
+// 切换到工作目录，目录不存在时与 docker 一样自动创建
+pub fn enter_workdir(workdir: &str) -> Result<()> {
+    std::fs::create_dir_all(workdir).context(format!("Failed to create working directory {}", workdir))?;
+    std::env::set_current_dir(workdir).context(format!("Failed to change directory to {}", workdir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|entry| entry.to_string()).collect()
+    }
+
+    #[test]
+    fn merge_keeps_position_and_takes_new_value() {
+        let base = strings(&["PATH=/bin", "HOME=/root", "A=1"]);
+        let merged = merge_env(base, &strings(&["HOME=/home/web", "B=2", "A=", "B=3"]));
+        assert_eq!(merged, ["PATH=/bin", "HOME=/home/web", "A=", "B=3"]);
+        // 只比较 = 之前的完整变量名
+        assert_eq!(merge_env(strings(&["AB=1"]), &strings(&["A=2"])), ["AB=1", "A=2"]);
+    }
+
+    #[test]
+    fn validates_entries() {
+        for entry in ["A=1", "A=", "A==b", "lower_case=x y"] {
+            assert!(validate_env(entry).is_ok(), "{:?} should be accepted", entry);
+        }
+        for entry in ["=1", "=", "A", "A=\0"] {
+            assert!(validate_env(entry).is_err(), "{:?} should be rejected", entry);
+        }
+    }
+
+    #[test]
+    fn resolves_env_files_and_overrides() {
+        let file = std::env::temp_dir().join(format!("mydocker-env-test-{}", std::process::id()));
+        std::fs::write(&file, "# comment\n\n  A=from-file  \nB=file\nMYDOCKER_TEST_UNSET_VAR\n").unwrap();
+        let env = resolve_env(&strings(&["B=cli", "C=3"]), &[file.to_str().unwrap().to_string()]).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        // 没有值且 mydocker 环境中也没有的变量被忽略
+        assert_eq!(env, ["A=from-file", "B=cli", "C=3"]);
+
+        assert!(resolve_env(&strings(&["=x"]), &[]).is_err());
+        assert!(resolve_env(&[], &strings(&["/nonexistent/env-file"])).is_err());
+    }
+
+    #[test]
+    fn searches_container_path() {
+        assert_eq!(command_paths("/bin/sh", &[]), ["/bin/sh"]);
+        assert_eq!(command_paths("./run.sh", &strings(&["PATH=/bin"])), ["./run.sh"]);
+        assert!(command_paths("", &[]).is_empty());
+        assert_eq!(command_paths("sh", &[]), DEFAULT_PATH.split(':').map(|dir| format!("{}/sh", dir)).collect::<Vec<_>>());
+        // 后面的 PATH 覆盖前面的，空的一项表示当前目录
+        assert_eq!(
+            command_paths("sh", &strings(&["PATH=/usr/bin", "PATH=/opt/bin/::/bin"])),
+            ["/opt/bin/sh", "sh", "/bin/sh"],
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use log::info;
+
+use crate::RunCommand;
+use crate::container::SHORT_ID_LEN;
+use crate::error::{Context, Error, Result};
+use crate::state::metainfo_base_path;
+
+const HOST_RESOLV_CONF: &str = "/etc/resolv.conf";
+// 容器没有可用的 nameserver 时与 docker 一样使用公共 DNS
+const DEFAULT_NAMESERVERS: [&str; 2] = ["8.8.8.8", "8.8.4.4"];
+
+// 为每个容器生成的 /etc 下的文件，保存在容器的元信息目录中，由 init 进程绑定挂载到容器的 /etc 下
+pub const ETC_FILES: [&str; 3] = ["hostname", "hosts", "resolv.conf"];
+
+pub fn etc_file_path(container_id: &str, name: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}/{}", metainfo_base_path(), container_id, name))
+}
+
+// 没有指定 --hostname 时与 docker 一样使用短 ID
+pub fn container_hostname(container_id: &str, command: &RunCommand) -> String {
+    command.hostname.clone().unwrap_or_else(|| container_id[..SHORT_ID_LEN.min(container_id.len())].to_string())
+}
+
+// 在创建容器之前检查 --hostname、--dns、--add-host 的格式
+pub fn validate_etc_options(command: &RunCommand) -> Result<()> {
+    if let Some(hostname) = &command.hostname {
+        validate_hostname(hostname)?;
+    }
+    for dns in &command.dns {
+        dns.parse::<IpAddr>().map_err(|_| Error::InvalidArgument(format!("Invalid DNS server {}", dns)))?;
+    }
+    for host in &command.add_host {
+        parse_host(host)?;
+    }
+    Ok(())
+}
+
+// 主机名由 '.' 分隔的若干段组成，每段只能包含字母、数字和 '-'，且不能以 '-' 开头或结尾
+fn validate_hostname(hostname: &str) -> Result<()> {
+    let valid = hostname.len() <= 253 && hostname.split('.').all(|label| {
+        !label.is_empty() && label.len() <= 63 && !label.starts_with('-') && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+    if !valid {
+        return Err(Error::InvalidArgument(format!("Invalid hostname {}", hostname)));
+    }
+    Ok(())
+}
+
+// --add-host 形如 name:ip，IPv6 地址中的 ':' 不影响解析
+fn parse_host(host: &str) -> Result<(&str, IpAddr)> {
+    host.split_once(':')
+        .filter(|(name, _)| validate_hostname(name).is_ok())
+        .and_then(|(name, ip)| Some((name, ip.parse::<IpAddr>().ok()?)))
+        .ok_or_else(|| Error::InvalidArgument(format!("Invalid host {}, expected name:ip", host)))
+}
+
+// 生成容器的 hostname、hosts 和 resolv.conf，ip 为容器在网络中的地址
+// 容器每次启动时重新生成，网络分配的地址可能变化
+pub fn write_etc_files(container_id: &str, command: &RunCommand, ip: Option<IpAddr>) -> Result<()> {
+    info!("Generating /etc files for container {}", container_id);
+    let hostname = container_hostname(container_id, command);
+    let dir = format!("{}{}", metainfo_base_path(), container_id);
+    std::fs::create_dir_all(&dir).context(format!("Failed to create {}", dir))?;
+
+    write_etc_file(container_id, "hostname", format!("{}\n", hostname))?;
+
+    // 没有连接网络时主机名解析到回环地址
+    let mut hosts = match ip {
+        Some(ip) => format!("127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n{}\t{}\n", ip, hostname),
+        None => format!("127.0.0.1\tlocalhost {}\n::1\tlocalhost ip6-localhost ip6-loopback\n", hostname),
+    };
+    for host in &command.add_host {
+        let (name, ip) = parse_host(host)?;
+        hosts += &format!("{}\t{}\n", ip, name);
+    }
+    write_etc_file(container_id, "hosts", hosts)?;
+
+    write_etc_file(container_id, "resolv.conf", resolv_conf(command)?)
+}
+
+fn write_etc_file(container_id: &str, name: &str, content: String) -> Result<()> {
+    let path = etc_file_path(container_id, name);
+    std::fs::write(&path, content).context(format!("Failed to write {}", path.display()))
+}
+
+// 没有指定 --dns 时沿用宿主机的 nameserver，但回环地址在容器的网络 namespace 中不可达，需要去掉
+// 指定了 --dns-search 时替换宿主机的 search，其余的选项（如 options ndots）原样保留
+fn resolv_conf(command: &RunCommand) -> Result<String> {
+    let host = match std::fs::read_to_string(HOST_RESOLV_CONF) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).context(format!("Failed to read {}", HOST_RESOLV_CONF)),
+    };
+
+    let mut nameservers = command.dns.clone();
+    let mut searches = command.dns_search.clone();
+    let mut options = vec![];
+    for line in host.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("nameserver") => {
+                if let Some(ip) = fields.next().and_then(|ip| ip.parse::<IpAddr>().ok())
+                    && !ip.is_loopback()
+                    && command.dns.is_empty()
+                {
+                    nameservers.push(ip.to_string());
+                }
+            }
+            Some("search") | Some("domain") if command.dns_search.is_empty() => {
+                searches.extend(fields.map(str::to_string));
+            }
+            Some("options") => options.push(line.trim().to_string()),
+            _ => {}
+        }
+    }
+    if nameservers.is_empty() {
+        nameservers = DEFAULT_NAMESERVERS.map(str::to_string).to_vec();
+    }
+
+    let mut content = String::new();
+    for nameserver in nameservers {
+        content += &format!("nameserver {}\n", nameserver);
+    }
+    if !searches.is_empty() {
+        content += &format!("search {}\n", searches.join(" "));
+    }
+    for line in options {
+        content += &format!("{}\n", line);
+    }
+    Ok(content)
+}
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Context, Result};
+use crate::state::image_base_path;
+
+// 镜像的默认配置，保存在镜像 tar 包旁边的 <image>.json 中，没有这个文件时使用空的配置
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ImageConfig {
+    pub env: Vec<String>,           // 形如 KEY=VALUE
+    pub workdir: Option<String>,
+}
+
+fn image_config_path(image: &str) -> String {
+    image_base_path() + image + ".json"
+}
+
+pub fn load_image_config(image: &str) -> Result<ImageConfig> {
+    let path = image_config_path(image);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ImageConfig::default()),
+        Err(e) => return Err(e).context(format!("Failed to read {}", path)),
+    };
+    serde_json::from_str(&content).context(format!("Failed to parse {}", path))
+}
+
+pub fn save_image_config(image: &str, config: &ImageConfig) -> Result<()> {
+    let path = image_config_path(image);
+    let content = serde_json::to_string_pretty(config).context("Failed to serialize image config")?;
+    std::fs::write(&path, content).context(format!("Failed to write {}", path))
+}
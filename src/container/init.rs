@@ -1,98 +1,214 @@
-use libc::{c_void, perror, syscall, SYS_pivot_root};
+use libc::{syscall, SYS_pivot_root};
 use log::{info, error};
+use nix::errno::Errno;
 use nix::mount::{mount, umount2, MntFlags, MsFlags};
-use nix::unistd::execvp;
+use nix::sched::{unshare, CloneFlags};
+use nix::unistd::{execve, sethostname};
 use std::{ffi::CString, path::Path, env::set_current_dir, fs::remove_dir_all};
-use std::os::fd::AsRawFd;
 
-use crate::run::{RunArg, ROOTFS_BASE_PATH};
-use crate::container::METAINFO_BASE_PATH;
-
-fn setup_mount(container_id: &str) -> Result<(), std::io::Error> {
-    mount(None::<&Path>, Path::new("/"), None::<&Path>, MsFlags::MS_PRIVATE | MsFlags::MS_REC, None::<&Path>)?;
-
-    let new_root_str = format!("{}{}/merged", ROOTFS_BASE_PATH, container_id);
+use crate::run::RunArg;
+use crate::seccomp::SeccompFilter;
+use crate::state::rootfs_base_path;
+use crate::container::{
+    become_root, command_paths, drop_bounding_set, enter_workdir, etc_file_path, limit_capabilities, mask_paths, merge_env,
+    readonly_paths, remount_readonly, resolve_user, setup_devices, setup_stdio, switch_user, ETC_FILES,
+};
+use crate::error::{Context, Error, Result};
+
+fn setup_mount(run_arg: &RunArg) -> Result<()> {
+    let container_id = &run_arg.container_id;
+    mount(None::<&Path>, Path::new("/"), None::<&Path>, MsFlags::MS_PRIVATE | MsFlags::MS_REC, None::<&Path>)
+        .context("Failed to make / a private mount")?;
+
+    let new_root_str = format!("{}{}/merged", rootfs_base_path(), container_id);
     let new_root = Path::new(&new_root_str);
+
+    // rootless 模式下宿主机上没有完成的挂载，在容器自己的 mount namespace 中完成
+    if let Some(options) = &run_arg.mounts.overlay {
+        mount(Some("overlay"), new_root, Some("overlay"), MsFlags::empty(), Some(options.as_str()))
+            .context("Failed to mount overlayfs")?;
+    }
+    if let Some((volume, mount_point)) = &run_arg.mounts.volume {
+        let target = new_root.join(mount_point.strip_prefix("/").unwrap_or(mount_point));
+        if !target.is_dir() {
+            return Err(Error::InvalidArgument(
+                format!("Mount point {} does not exist or is not a directory", mount_point.display())
+            ));
+        }
+        mount(Some(volume.as_path()), &target, None::<&Path>, MsFlags::MS_BIND | MsFlags::MS_REC, None::<&Path>)
+            .context(format!("Failed to bind mount {} to {}", volume.display(), target.display()))?;
+    }
     // pivot_root(new_root, put_old) 的要求之一是：
     // new_root 和 put_old（旧的根目录）必须处于不同的挂载点（mount point）上，也就是：
     // 不能是同一个文件系统（same mount）这是为了避免死循环或“移动自己”这种不可预测的行为。你不能把一个目录挂到它自己内部。
     // 将 new_root 绑定挂载为一个新的 mount point 即可，哪怕它本质上和 old_root 还是在同一个文件系统中
     // 使用 MS_BIND 和 MS_REC 选项来实现这个自己挂载到自己的递归式的绑定挂载
-    mount(Some(new_root), new_root, None::<&Path>, MsFlags::MS_BIND | MsFlags::MS_REC, None::<&Path>)?;
+    mount(Some(new_root), new_root, None::<&Path>, MsFlags::MS_BIND | MsFlags::MS_REC, None::<&Path>)
+        .context(format!("Failed to bind mount {}", new_root_str))?;
 
     // 新建目录 .old_root
-    let old_root_str = format!("{}{}/merged/.old_root", ROOTFS_BASE_PATH, container_id);
+    let old_root_str = format!("{}{}/merged/.old_root", rootfs_base_path(), container_id);
     let old_root = Path::new(&old_root_str);
     if !old_root.exists() {
-        std::fs::create_dir_all(old_root)?;
+        std::fs::create_dir_all(old_root).context(format!("Failed to create {}", old_root_str))?;
     }
 
-    unsafe {
-        info!("executing pivot_root, change rootfs");
-        let c_new_root = CString::new(new_root_str).unwrap();
-        let c_old_root = CString::new(old_root_str).unwrap();
-        if syscall(SYS_pivot_root, c_new_root.as_ptr(), c_old_root.as_ptr()) < 0 {
-            error!("Error: pivot_root failed");
-            perror(std::ptr::null());
-            std::process::exit(1);
-        }
+    for name in ETC_FILES {
+        bind_etc_file(new_root, &etc_file_path(container_id, name), name)?;
+    }
+
+    // 在 pivot_root 之前挂载 proc 和 sysfs：user namespace 中只有当前 mount namespace 里还能看到完整的 proc 时才允许挂载新的 proc
+    for spec in &run_arg.mount_specs {
+        spec.mount(new_root)?;
+    }
+    setup_devices(new_root, &run_arg.devices, run_arg.stdio, run_arg.userns)?;
+
+    info!("executing pivot_root, change rootfs");
+    let c_new_root = CString::new(new_root_str).map_err(std::io::Error::from).context("Invalid rootfs path")?;
+    let c_old_root = CString::new(old_root_str).map_err(std::io::Error::from).context("Invalid rootfs path")?;
+    if unsafe { syscall(SYS_pivot_root, c_new_root.as_ptr(), c_old_root.as_ptr()) } < 0 {
+        return Err(Errno::last()).context("pivot_root failed");
     }
 
     // 切换到新的根目录
-    set_current_dir("/")?;
+    set_current_dir("/").context("Failed to change directory to /")?;
 
     // 卸载旧的根目录
-    umount2("/.old_root", MntFlags::MNT_DETACH)?;
+    umount2("/.old_root", MntFlags::MNT_DETACH).context("Failed to unmount old root")?;
 
     // 删除旧的根目录
-    remove_dir_all("/.old_root")?;
+    remove_dir_all("/.old_root").context("Failed to remove old root")?;
 
-    // 挂载 proc 文件系统
-    mount(Some("/proc"), "/proc", Some("proc"), MsFlags::empty(), None::<&Path>)?;
+    if !run_arg.privileged {
+        mask_paths()?;
+        readonly_paths()?;
+    }
+    if run_arg.read_only {
+        // 根目录只读之后无法再创建工作目录
+        std::fs::create_dir_all(&run_arg.workdir).context(format!("Failed to create working directory {}", run_arg.workdir))?;
+        remount_readonly("/")?;
+    }
+
+    Ok(())
+}
 
-    // 挂载 dev 文件系统
-    mount(Some("/dev"), "/dev", Some("devtmpfs"), MsFlags::empty(), None::<&Path>)?;
-    
+// 把元信息目录中生成的文件绑定挂载到容器的 /etc 下
+// 镜像中的 /etc/resolv.conf 等可能是符号链接，pivot_root 之前解析会指向宿主机上的文件，所以先替换为普通文件
+fn bind_etc_file(new_root: &Path, source: &Path, name: &str) -> Result<()> {
+    let etc = new_root.join("etc");
+    std::fs::create_dir_all(&etc).context(format!("Failed to create {}", etc.display()))?;
+    let target = etc.join(name);
+    match std::fs::symlink_metadata(&target) {
+        Ok(metadata) if metadata.is_file() => {}
+        Ok(_) => {
+            std::fs::remove_file(&target).context(format!("Failed to remove {}", target.display()))?;
+            std::fs::File::create(&target).context(format!("Failed to create {}", target.display()))?;
+        }
+        Err(_) => {
+            std::fs::File::create(&target).context(format!("Failed to create {}", target.display()))?;
+        }
+    }
+    mount(Some(source), &target, None::<&Path>, MsFlags::MS_BIND, None::<&Path>)
+        .context(format!("Failed to bind mount {} to {}", source.display(), target.display()))?;
     Ok(())
 }
 
-fn setup_log(container_id: &str) {
-    let log_path = format!("{}{}/container.log", METAINFO_BASE_PATH, container_id);
-    info!("Redirecting stdout and stderr to {}", log_path);
-    let log_file = std::fs::OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(&log_path)
-        .unwrap_or_else(|e| panic!("Failed to open log file {}: {}", log_path, e));
+// 将命令和参数转换为 execve 需要的 argv，参数中不能含有 '\0'
+pub fn command_cstrings(command: &str, args: &[String]) -> Result<Vec<CString>> {
+    std::iter::once(command).chain(args.iter().map(String::as_str))
+        .map(|arg| CString::new(arg)
+            .map_err(|_| Error::InvalidArgument(format!("Invalid argument {:?}: contains a nul byte", arg))))
+        .collect()
+}
 
-    let fd = log_file.as_raw_fd();
+// 在容器的 PATH 中查找命令并 execve，成功时不会返回
+// 环境变量通过 envp 传给新程序，而不是 clearenv 之后 set_var：clone 出的子进程可能来自多线程的父进程，
+// 其他线程持有的环境变量锁在子进程中永远不会被释放
+// 与 execvp 一样，找不到命令时返回 ENOENT，有候选路径没有执行权限时返回 EACCES
+pub fn exec_command(command: &str, argv: &[CString], env: &[String]) -> Error {
+    let envp = match env.iter()
+        .map(|entry| CString::new(entry.as_str())
+            .map_err(|_| Error::InvalidArgument(format!("Invalid environment variable {:?}", entry))))
+        .collect::<Result<Vec<_>>>() {
+        Ok(envp) => envp,
+        Err(e) => return e,
+    };
+    let mut result = Errno::ENOENT;
+    for path in command_paths(command, env) {
+        let Ok(path) = CString::new(path) else { continue };
+        let Err(errno) = execve(&path, argv, &envp);
+        match errno {
+            Errno::EACCES => result = Errno::EACCES,
+            // 与 execvp 一样，这些错误说明不是这个候选路径，继续查找 PATH 中的下一项
+            Errno::ENOENT | Errno::ENOTDIR | Errno::ELOOP | Errno::ENAMETOOLONG | Errno::ESTALE | Errno::ENODEV
+            | Errno::ETIMEDOUT => {}
+            errno => return Error::exec(command, errno),
+        }
+    }
+    Error::exec(command, result)
+}
 
-    unsafe {
-        libc::dup2(fd, 1); // stdout
-        libc::dup2(fd, 2); // stderr
+// pivot_root 或者 exec 进入容器的 namespace 之后、execve 之前调用，设置进程的身份、capability 和工作目录
+// 返回传给 execve 的环境变量。没有指定 -u 时仍以 root 运行，HOME 取自容器的 /etc/passwd，可以被 -e 覆盖
+pub fn prepare_exec(
+    user: Option<&str>,
+    workdir: &str,
+    env: &[String],
+    capabilities: u64,
+    seccomp: Option<&SeccompFilter>,
+) -> Result<Vec<String>> {
+    let exec_user = resolve_user(user.unwrap_or("0"))?;
+    // 以 root 身份创建工作目录，之后再切换用户
+    enter_workdir(workdir)?;
+    // 缩小 bounding set 需要 CAP_SETPCAP，切换用户需要 CAP_SETUID 和 CAP_SETGID，所以最后才限制 effective
+    drop_bounding_set(capabilities)?;
+    if user.is_some() {
+        switch_user(&exec_user)?;
     }
+    limit_capabilities(capabilities)?;
+    let env = merge_env(vec![format!("HOME={}", exec_user.home)], env);
+    // 与 runc 一样在 execve 之前最后安装，过滤器只作用于容器中的程序，不会拦下切换身份和 capability 的系统调用
+    // 此时通常已经没有 CAP_SYS_ADMIN，install 会先设置 no_new_privs
+    if let Some(seccomp) = seccomp {
+        seccomp.install()?;
+    }
+    Ok(env)
 }
 
-pub extern "C" fn init_process(arg: *mut c_void) -> i32 {
-    let run_arg_ref = unsafe { &*(arg as *mut RunArg) };
-    info!("Init process started with command {}", run_arg_ref.command);
+// 在 clone 出的容器进程中执行，返回值即容器进程的退出码，execve 失败时与 docker 一样返回 126 或 127
+pub fn init_process(run_arg: &RunArg) -> i32 {
+    info!("Init process started with command {}", run_arg.command);
 
-    if run_arg_ref.detach {
-        setup_log(&run_arg_ref.container_id);
-    }
-    
-    match setup_mount(&run_arg_ref.container_id) {
-        Ok(_) => {},
+    match init_container(run_arg) {
+        Ok(()) => 0,
         Err(e) => {
-            error!("Error: mount setup failed: {}", e);
-            return -1;
+            error!("Error: {}", e);
+            let code = e.exit_code();
+            run_arg.sync.report(e);
+            code
         }
     }
+}
+
+fn init_container(run_arg: &RunArg) -> Result<()> {
+    if let Some(stdio) = run_arg.stdio {
+        setup_stdio(stdio).context("Failed to set up stdio")?;
+    }
+
+    // 等待父进程把自己加入 cgroup、写好 ID 映射并配置好网络
+    run_arg.sync.wait_resume()?;
+    if run_arg.userns {
+        become_root()?;
+    }
+    // cgroup namespace 以创建时所在的 cgroup 为根，所以不在 clone 时创建，而是等父进程把自己加入容器的 cgroup 之后再创建
+    unshare(CloneFlags::CLONE_NEWCGROUP).context("Failed to create cgroup namespace")?;
+    sethostname(&run_arg.hostname).context("Failed to set hostname")?;
 
-    let args = std::iter::once(&run_arg_ref.command).chain(run_arg_ref.args.iter())
-        .map(|arg| CString::new(arg.clone()).unwrap())
-        .collect::<Vec<_>>();
-    execvp(&CString::new(run_arg_ref.command.clone()).unwrap(), args.as_slice()).expect("execvp failed");
+    setup_mount(run_arg)?;
+    let env = prepare_exec(
+        run_arg.user.as_deref(), &run_arg.workdir, &run_arg.env, run_arg.capabilities, run_arg.seccomp.as_ref(),
+    )?;
 
-    return 0;
+    let argv = command_cstrings(&run_arg.command, &run_arg.args)?;
+    Err(exec_command(&run_arg.command, &argv, &env))
 }
\ No newline at end of file
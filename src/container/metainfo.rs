@@ -1,121 +1,617 @@
 use serde::{Serialize, Deserialize};
-use log::info;
+use log::{error, info};
 use rand::prelude::*;
+use std::collections::BTreeMap;
+use std::io::Write;
+use chrono::{DateTime, Local, TimeDelta};
 
 use crate::RunCommand;
 use crate::PsCommand;
-
-pub const METAINFO_BASE_PATH: &str = "/root/.mydocker/containers/";
+use crate::network::Endpoint;
+use crate::error::{Context, Error, Result};
+use crate::state::{metainfo_base_path, names_base_path, short_ids_base_path};
+use crate::container::container_capabilities;
+use crate::shim::cleanup_container;
 
 #[derive(Serialize, Deserialize)]
 struct Metainfo {
     pid: Option<u32>,
+    #[serde(default)]
+    start_time: Option<u64>,                // 容器进程的启动时间，用于识别 PID 被复用的情况
     id: String,
     command: RunCommand,
     status: String,
+    #[serde(default = "Local::now")]
+    created_at: DateTime<Local>,
+    #[serde(default)]
+    started_at: Option<DateTime<Local>>,
+    #[serde(default)]
+    finished_at: Option<DateTime<Local>>,
+    #[serde(default)]
+    exit_code: Option<i32>,                 // 被信号杀死时记为 128 + 信号值
+    #[serde(default)]
+    signal: Option<String>,                 // 杀死容器进程的信号，如 SIGKILL
+    #[serde(default)]
+    oom_killed: bool,
+    #[serde(default)]
+    endpoint: Option<Endpoint>,             // 连接到网络时分配的 IP 和 veth 信息
+    #[serde(default)]
+    capabilities: Option<Vec<String>>,      // 容器进程保留的 capability，exec 的进程与之相同
+    #[serde(default)]
+    supervisor_pid: Option<u32>,            // 负责回收容器进程的 shim 或前台的 run
+    #[serde(default)]
+    supervisor_start_time: Option<u64>,
+}
+
+// 容器进程的退出状态，由 waitpid 的结果和 cgroup 的 memory.events 得出
+pub struct ExitStatus {
+    pub code: i32,
+    pub signal: Option<String>,
+    pub oom_killed: bool,
 }
 
-pub fn init_metainfo(container_id: &str, pid: u32, command: RunCommand) -> String {
+pub fn init_metainfo(container_id: &str, pid: u32, command: RunCommand, capabilities: Vec<String>) -> Result<()> {
+    let now = Local::now();
     let metainfo = Metainfo {
         pid: Some(pid),
+        start_time: process_start_time(pid),
         id: container_id.to_string(),
         command,
         status: "running".to_string(),
+        created_at: now,
+        started_at: Some(now),
+        finished_at: None,
+        exit_code: None,
+        signal: None,
+        oom_killed: false,
+        endpoint: None,
+        capabilities: Some(capabilities),
+        // init_metainfo 在 supervisor 中调用
+        supervisor_pid: Some(std::process::id()),
+        supervisor_start_time: process_start_time(std::process::id()),
     };
-    let metainfo_dir = format!("{}{}/", METAINFO_BASE_PATH, metainfo.id);
-    std::fs::create_dir_all(&metainfo_dir).expect("Failed to create metainfo directory");
-    let metainfo_file = metainfo_dir + "config.json";
-    let metainfo_json = serde_json::to_string(&metainfo).expect("Failed to serialize metainfo");
-    std::fs::write(&metainfo_file, metainfo_json).expect("Failed to write metainfo file");
-    info!("Metainfo file created at {}", metainfo_file);
-    return metainfo.id;
+    let metainfo_dir = format!("{}{}/", metainfo_base_path(), metainfo.id);
+    std::fs::create_dir_all(&metainfo_dir)
+        .context(format!("Failed to create metainfo directory {}", metainfo_dir))?;
+    save_metainfo(&metainfo)?;
+    info!("Metainfo file created at {}config.json", metainfo_dir);
+    Ok(())
 }
 
-pub fn delete_metainfo(container_id: &str) {
-    let metainfo_dir = format!("{}{}/", METAINFO_BASE_PATH, container_id);
-    std::fs::remove_dir_all(&metainfo_dir).expect("Failed to delete metainfo directory");
+pub fn delete_metainfo(container_id: &str) -> Result<()> {
+    let metainfo_dir = format!("{}{}/", metainfo_base_path(), container_id);
+    let name = read_metainfo(&format!("{}config.json", metainfo_dir)).ok().and_then(|metainfo| metainfo.command.name);
+    release_container(container_id, name.as_deref())?;
+    std::fs::remove_dir_all(&metainfo_dir)
+        .context(format!("Failed to delete metainfo directory {}", metainfo_dir))
 }
 
+// ps 等命令中显示的短 ID 长度
+pub const SHORT_ID_LEN: usize = 12;
+
+// 生成 32 位十六进制的容器 ID，并保证短 ID 不与已有的容器重复
 pub fn gen_id() -> String {
     let mut rng = rand::rng();
-    let nums: Vec<i32> = (0..10).collect();
-    let mut id = String::new();
-    for _ in 0..10 {
-        id.push_str(&nums.choose(&mut rng).unwrap().to_string());
+    let existing = list_metainfo();
+    loop {
+        let id = rng.random::<[u8; 16]>().iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        let short_id = &id[..SHORT_ID_LEN];
+        if !existing.iter().any(|metainfo| metainfo.id.starts_with(short_id))
+            && !std::fs::exists(format!("{}{}", short_ids_base_path(), short_id)).unwrap_or(true)
+        {
+            return id;
+        }
+    }
+}
+
+// 在创建工作空间等资源之前占用容器的短 ID 和名字，删除容器时释放
+// 以 O_EXCL 创建文件，同时创建的两个容器只有一个能成功，检查和占用之间没有空隙
+pub fn reserve_container(container_id: &str, name: Option<&str>) -> Result<()> {
+    let short_id = &container_id[..SHORT_ID_LEN];
+    if !reserve(&short_ids_base_path(), short_id, container_id)? {
+        return Err(Error::Conflict(format!("The container ID {} is already in use", short_id)));
+    }
+    if let Some(name) = name
+        && !reserve(&names_base_path(), name, container_id)?
+    {
+        release(&short_ids_base_path(), short_id, container_id)?;
+        return Err(Error::Conflict(format!("The container name \"{}\" is already in use", name)));
+    }
+    Ok(())
+}
+
+pub fn release_container(container_id: &str, name: Option<&str>) -> Result<()> {
+    release(&short_ids_base_path(), &container_id[..SHORT_ID_LEN.min(container_id.len())], container_id)?;
+    match name {
+        Some(name) => release(&names_base_path(), name, container_id),
+        None => Ok(()),
+    }
+}
+
+// 文件内容为占用者的完整 ID，返回 false 表示已经被其他容器占用
+fn reserve(dir: &str, key: &str, container_id: &str) -> Result<bool> {
+    std::fs::create_dir_all(dir).context(format!("Failed to create {}", dir))?;
+    let path = format!("{}{}", dir, key);
+    match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(mut file) => {
+            file.write_all(container_id.as_bytes()).context(format!("Failed to write {}", path))?;
+            Ok(true)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e).context(format!("Failed to create {}", path)),
+    }
+}
+
+// 只释放自己占用的，已经不存在时忽略
+fn release(dir: &str, key: &str, container_id: &str) -> Result<()> {
+    let path = format!("{}{}", dir, key);
+    match std::fs::read_to_string(&path) {
+        Ok(owner) if owner == container_id => std::fs::remove_file(&path).context(format!("Failed to remove {}", path)),
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).context(format!("Failed to read {}", path)),
+        _ => Ok(()),
     }
-    id
 }
 
-pub fn record_exit(container_id: &str) {
-    let metainfo_file = format!("{}{}/config.json", METAINFO_BASE_PATH, container_id);
+// 容器名与 docker 的规则一致：字母或数字开头，之后可以包含字母、数字、'_'、'.'、'-'
+pub fn validate_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c));
+    if !valid {
+        return Err(Error::InvalidArgument(
+            format!("Invalid container name \"{}\", only [a-zA-Z0-9][a-zA-Z0-9_.-] are allowed", name)
+        ));
+    }
+    if list_metainfo().iter().any(|metainfo| metainfo.command.name.as_deref() == Some(name)) {
+        return Err(Error::Conflict(format!("The container name \"{}\" is already in use", name)));
+    }
+    Ok(())
+}
 
-    // use serde_json to read metainfo file
-    let metainfo_content = std::fs::read_to_string(&metainfo_file).expect("Failed to read metainfo file");
-    let mut metainfo: Metainfo = serde_json::from_str(&metainfo_content).expect("Failed to deserialize metainfo");
+// 将用户输入的容器名、完整 ID 或者 ID 前缀解析为完整的容器 ID
+pub fn resolve_container_id(name_or_id: &str) -> Result<String> {
+    let containers = list_metainfo();
+    if let Some(metainfo) = containers.iter().find(|metainfo| metainfo.id == name_or_id) {
+        return Ok(metainfo.id.clone());
+    }
+    if let Some(metainfo) = containers.iter().find(|metainfo| metainfo.command.name.as_deref() == Some(name_or_id)) {
+        return Ok(metainfo.id.clone());
+    }
+    let matches = containers.iter()
+        .filter(|metainfo| !name_or_id.is_empty() && metainfo.id.starts_with(name_or_id))
+        .collect::<Vec<_>>();
+    match matches.len() {
+        0 => Err(Error::NoSuchContainer(name_or_id.to_string())),
+        1 => Ok(matches[0].id.clone()),
+        _ => Err(Error::InvalidArgument(
+            format!("Multiple containers match ID prefix {}, please use a longer one", name_or_id)
+        )),
+    }
+}
+
+// 先写到临时文件再 rename，supervisor 记录退出状态时即使有其他进程同时在写，也不会留下写了一半的文件
+fn save_metainfo(metainfo: &Metainfo) -> Result<()> {
+    let metainfo_file = format!("{}{}/config.json", metainfo_base_path(), metainfo.id);
+    let tmp_file = format!("{}.{}", metainfo_file, std::process::id());
+    let metainfo_json = serde_json::to_string(metainfo).context("Failed to serialize metainfo")?;
+    std::fs::write(&tmp_file, metainfo_json).context(format!("Failed to write {}", tmp_file))?;
+    std::fs::rename(&tmp_file, &metainfo_file).context(format!("Failed to write {}", metainfo_file))
+}
+
+fn read_metainfo(metainfo_file: &str) -> Result<Metainfo> {
+    let metainfo_content = std::fs::read_to_string(metainfo_file).context(format!("Failed to read {}", metainfo_file))?;
+    serde_json::from_str(&metainfo_content).context(format!("Failed to parse {}", metainfo_file))
+}
 
+// 标记为 exited，退出码等由调用者填写
+fn mark_exited(metainfo: &mut Metainfo) {
     metainfo.status = "exited".to_string();
     metainfo.pid = None;
+    metainfo.start_time = None;
+    metainfo.supervisor_pid = None;
+    metainfo.supervisor_start_time = None;
+    metainfo.finished_at = Some(Local::now());
+}
+
+pub fn record_exit(container_id: &str, exit_status: ExitStatus) -> Result<()> {
+    let mut metainfo = get_metainfo(container_id)?;
 
-    let metainfo_json = serde_json::to_string(&metainfo).expect("Failed to serialize metainfo");
-    std::fs::write(&metainfo_file, metainfo_json).expect("Failed to write metainfo file");
+    mark_exited(&mut metainfo);
+    metainfo.exit_code = Some(exit_status.code);
+    metainfo.signal = exit_status.signal;
+    metainfo.oom_killed = exit_status.oom_killed;
+
+    save_metainfo(&metainfo)
 }
 
-pub fn record_running(container_id: &str, pid: u32) {
-    let metainfo_file = format!("{}{}/config.json", METAINFO_BASE_PATH, container_id);
+pub fn record_running(container_id: &str, pid: u32) -> Result<()> {
+    let metainfo_file = format!("{}{}/config.json", metainfo_base_path(), container_id);
 
-    // use serde_json to read metainfo file
-    let metainfo_content = std::fs::read_to_string(&metainfo_file).expect("Failed to read metainfo file");
-    let mut metainfo: Metainfo = serde_json::from_str(&metainfo_content).expect("Failed to deserialize metainfo");
+    // 直接读取文件而不经过 load_metainfo，容器重新启动之前的状态必然是 exited
+    let mut metainfo = read_metainfo(&metainfo_file)?;
 
     metainfo.status = "running".to_string();
     metainfo.pid = Some(pid);
+    metainfo.start_time = process_start_time(pid);
+    metainfo.supervisor_pid = Some(std::process::id());
+    metainfo.supervisor_start_time = process_start_time(std::process::id());
+    metainfo.started_at = Some(Local::now());
+    metainfo.finished_at = None;
+    metainfo.exit_code = None;
+    metainfo.signal = None;
+    metainfo.oom_killed = false;
+    metainfo.endpoint = None;
+
+    save_metainfo(&metainfo)
+}
 
-    let metainfo_json = serde_json::to_string(&metainfo).expect("Failed to serialize metainfo");
-    std::fs::write(&metainfo_file, metainfo_json).expect("Failed to write metainfo file");
+pub fn record_endpoint(container_id: &str, endpoint: &Endpoint) -> Result<()> {
+    let mut metainfo = get_metainfo(container_id)?;
+    metainfo.endpoint = Some(endpoint.clone());
+    save_metainfo(&metainfo)
 }
 
-pub fn ps(command: PsCommand) {
-    if command.all {
-        // TODO: Implement logic to show all containers
+pub fn ps(command: PsCommand) -> Result<()> {
+    let filters = parse_filters(&command.filter)?;
+    // 默认只显示正在运行的容器，-a 或按状态过滤时显示全部
+    let show_exited = command.all || filters.contains_key("status");
+
+    let mut containers = list_metainfo();
+    containers.sort_by_key(|metainfo| std::cmp::Reverse(metainfo.created_at));
+    let containers = containers.into_iter()
+        .filter(|metainfo| show_exited || metainfo.status == "running")
+        .filter(|metainfo| matches_filters(metainfo, &filters))
+        .collect::<Vec<_>>();
+
+    if command.quiet {
+        for metainfo in &containers {
+            println!("{}", &metainfo.id[..SHORT_ID_LEN.min(metainfo.id.len())]);
+        }
+        return Ok(());
     }
-    let metainfo_dir = std::fs::read_dir(METAINFO_BASE_PATH).expect("Failed to read metainfo directory");
-    for entry in metainfo_dir {
-        let entry = entry.expect("Failed to read entry");
-        let path = entry.path();
-        if path.is_dir() {
-            let config_file = path.join("config.json");
-            if config_file.exists() {
-                let config_content = std::fs::read_to_string(config_file).expect("Failed to read config file");
-                println!("{}", config_content);
+
+    match command.format.as_deref() {
+        None => {},
+        Some("json") => {
+            // 每行一个 JSON 对象，与 docker ps --format json 一致
+            for metainfo in &containers {
+                let summary = serde_json::json!({
+                    "id": metainfo.id,
+                    "image": metainfo.command.image,
+                    "command": command_line(metainfo),
+                    "created_at": metainfo.created_at,
+                    "status": metainfo.status,
+                    "description": status_description(metainfo),
+                    "exit_code": metainfo.exit_code,
+                    "name": metainfo.command.name,
+                });
+                println!("{}", summary);
             }
+            return Ok(());
+        }
+        Some(format) => {
+            return Err(Error::InvalidArgument(format!("Unsupported format: {}", format)));
+        }
+    }
+
+    let headers = ["CONTAINER ID", "IMAGE", "COMMAND", "CREATED", "STATUS", "NAMES"];
+    let rows = containers.iter()
+        .map(|metainfo| vec![
+            metainfo.id[..SHORT_ID_LEN.min(metainfo.id.len())].to_string(),
+            metainfo.command.image.clone(),
+            format!("\"{}\"", truncate(&command_line(metainfo), 20)),
+            format!("{} ago", human_duration(Local::now() - metainfo.created_at)),
+            status_description(metainfo),
+            metainfo.command.name.clone().unwrap_or_default(),
+        ])
+        .collect::<Vec<_>>();
+    print_table(&headers, &rows);
+    Ok(())
+}
+
+fn list_metainfo() -> Vec<Metainfo> {
+    let mut containers = vec![];
+    let metainfo_dir = match std::fs::read_dir(metainfo_base_path()) {
+        Ok(dir) => dir,
+        Err(_) => return containers,    // 还没有创建过容器
+    };
+    // 元信息损坏的容器不影响其他容器，记录日志后跳过
+    for entry in metainfo_dir.flatten() {
+        let config_file = entry.path().join("config.json");
+        if !config_file.exists() {
+            continue;
+        }
+        match load_metainfo(&config_file.to_string_lossy()) {
+            Ok(metainfo) => containers.push(metainfo),
+            Err(e) => error!("{}", e),
+        }
+    }
+    containers
+}
+
+// --filter 的参数形如 key=value，按 key 分组
+fn parse_filters(filters: &[String]) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut grouped = BTreeMap::<String, Vec<String>>::new();
+    for filter in filters {
+        let (key, value) = filter.split_once('=')
+            .ok_or_else(|| Error::InvalidArgument(format!("Invalid filter '{}', expected key=value", filter)))?;
+        if !["status", "name", "ancestor"].contains(&key) {
+            return Err(Error::InvalidArgument(format!("Invalid filter '{}'", key)));
+        }
+        grouped.entry(key.to_string()).or_default().push(value.to_string());
+    }
+    Ok(grouped)
+}
+
+// 与 docker 一样，同一个 key 的多个值满足其一即可，不同的 key 都要满足
+fn matches_filters(metainfo: &Metainfo, filters: &BTreeMap<String, Vec<String>>) -> bool {
+    filters.iter().all(|(key, values)| values.iter().any(|value| matches_filter(metainfo, key, value)))
+}
+
+fn matches_filter(metainfo: &Metainfo, key: &str, value: &str) -> bool {
+    match key {
+        "status" => metainfo.status == value,
+        "name" => metainfo.command.name.as_deref().is_some_and(|name| name.contains(value)),
+        "ancestor" => metainfo.command.image == value,
+        _ => false,
+    }
+}
+
+fn command_line(metainfo: &Metainfo) -> String {
+    std::iter::once(&metainfo.command.command)
+        .chain(metainfo.command.args.iter())
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn truncate(s: &str, max_chars: usize) -> String {
+    if s.chars().count() <= max_chars {
+        return s.to_string();
+    }
+    let mut truncated = s.chars().take(max_chars - 1).collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+// 按每列最长的内容对齐，列之间空三格
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths = headers.iter().map(|header| header.chars().count()).collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
         }
     }
+    let format_row = |cells: Vec<&str>| {
+        let line = cells.iter().zip(&widths)
+            .map(|(cell, width)| format!("{}{}", cell, " ".repeat(width - cell.chars().count())))
+            .collect::<Vec<_>>()
+            .join("   ");
+        line.trim_end().to_string()
+    };
+    println!("{}", format_row(headers.to_vec()));
+    for row in rows {
+        println!("{}", format_row(row.iter().map(String::as_str).collect()));
+    }
+}
+
+// 与 docker ps 的 STATUS 列类似，如 "Up 3 minutes"、"Exited (137) 5 seconds ago (OOM killed)"
+fn status_description(metainfo: &Metainfo) -> String {
+    let now = Local::now();
+    if metainfo.status == "running" {
+        let started_at = metainfo.started_at.unwrap_or(metainfo.created_at);
+        return format!("Up {}", human_duration(now - started_at));
+    }
+    let mut description = match metainfo.exit_code {
+        Some(code) => format!("Exited ({})", code),
+        None => "Exited".to_string(),
+    };
+    if let Some(finished_at) = metainfo.finished_at {
+        description += &format!(" {} ago", human_duration(now - finished_at));
+    }
+    if metainfo.oom_killed {
+        description += " (OOM killed)";
+    }
+    description
+}
+
+fn human_duration(duration: TimeDelta) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (value, unit) = match seconds {
+        0..60 => (seconds, "second"),
+        60..3600 => (seconds / 60, "minute"),
+        3600..86400 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    if value == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", value, unit)
+    }
+}
+
+// 供 inspect 使用，返回完整的元信息
+pub fn metainfo_json(container_id: &str) -> Result<serde_json::Value> {
+    serde_json::to_value(get_metainfo(container_id)?).context("Failed to serialize metainfo")
 }
 
-fn get_metainfo(container_id: &str) -> Metainfo {
-    let metainfo_file = format!("{}{}/config.json", METAINFO_BASE_PATH, container_id);
-    let metainfo_content = std::fs::read_to_string(&metainfo_file).expect("Failed to read metainfo file");
-    let metainfo: Metainfo = serde_json::from_str(&metainfo_content).expect("Failed to deserialize metainfo");
-    return metainfo;
+fn get_metainfo(container_id: &str) -> Result<Metainfo> {
+    let metainfo_file = format!("{}{}/config.json", metainfo_base_path(), container_id);
+    if !metainfo_exists(container_id) {
+        return Err(Error::NoSuchContainer(container_id.to_string()));
+    }
+    load_metainfo(&metainfo_file)
 }
 
-pub fn get_pid(container_id: &str) -> u32 {
-    get_metainfo(container_id).pid.unwrap()
+// 读取元信息时顺带校验 running 状态是否属实：
+// 宿主机重启、shim 异常退出等情况下容器进程已经不在了，但没有人记录 exited 状态，也没有人清理容器占用的资源
+fn load_metainfo(metainfo_file: &str) -> Result<Metainfo> {
+    let mut metainfo = read_metainfo(metainfo_file)?;
+    if is_orphaned(&metainfo) {
+        info!("Container {} and its supervisor are gone, cleaning up", metainfo.id);
+        reap_orphan(&mut metainfo, None, None)?;
+    }
+    Ok(metainfo)
 }
 
-pub fn is_running(container_id: &str) -> bool {
-    get_metainfo(container_id).status == "running"
+// 容器进程和它的 supervisor 都已经不在了
+// supervisor 还在时即使容器进程已经退出，也由 supervisor 负责清理和记录退出状态
+fn is_orphaned(metainfo: &Metainfo) -> bool {
+    metainfo.status == "running"
+        && !is_alive(metainfo.pid, metainfo.start_time)
+        && !is_alive(metainfo.supervisor_pid, metainfo.supervisor_start_time)
 }
 
-pub fn get_volume(container_id: &str) -> Option<String> {
-    get_metainfo(container_id).command.volume
+// 代替 supervisor 清理容器占用的资源并记录退出状态，过程与 supervise 相同
+// 断开网络时会再次读取元信息，所以先保存 exited 状态，避免再次进入这里
+fn reap_orphan(metainfo: &mut Metainfo, exit_code: Option<i32>, signal: Option<String>) -> Result<()> {
+    mark_exited(metainfo);
+    metainfo.exit_code = exit_code;
+    metainfo.signal = signal;
+    save_metainfo(metainfo)?;
+    metainfo.oom_killed = cleanup_container(&metainfo.id, &metainfo.command);
+    save_metainfo(metainfo)
 }
 
-pub fn get_command(container_id: &str) -> RunCommand {
-    get_metainfo(container_id).command
+// 容器是否还由 supervisor 管理，即还没有记录 exited 状态并且 supervisor 还活着
+pub fn is_supervised(container_id: &str) -> bool {
+    let metainfo_file = format!("{}{}/config.json", metainfo_base_path(), container_id);
+    read_metainfo(&metainfo_file).is_ok_and(|metainfo| {
+        metainfo.status == "running" && is_alive(metainfo.supervisor_pid, metainfo.supervisor_start_time)
+    })
+}
+
+// supervisor 已经不在时由 stop 代为清理，记录杀死容器的信号；supervisor 还在或者已经记录了退出状态时什么也不做
+pub fn reap_container(container_id: &str, exit_status: ExitStatus) -> Result<()> {
+    let metainfo_file = format!("{}{}/config.json", metainfo_base_path(), container_id);
+    let mut metainfo = read_metainfo(&metainfo_file)?;
+    if !is_orphaned(&metainfo) {
+        return Ok(());
+    }
+    info!("Supervisor of container {} is gone, cleaning up", container_id);
+    reap_orphan(&mut metainfo, Some(exit_status.code), exit_status.signal)
+}
+
+// 进程存在且启动时间与记录的一致，才认为进程还活着
+fn is_alive(pid: Option<u32>, start_time: Option<u64>) -> bool {
+    let pid = match pid {
+        Some(pid) => pid,
+        None => return false,
+    };
+    match (process_start_time(pid), start_time) {
+        (None, _) => false,
+        (Some(current), Some(recorded)) => current == recorded,
+        (Some(_), None) => true,    // 旧版本没有记录启动时间，只能相信 PID
+    }
+}
+
+// /proc/<pid>/stat 的第 22 列，进程在系统启动后多少个 clock tick 时启动
+// 第 2 列是用括号括起来的进程名，可能包含空格，所以从最后一个 ')' 之后开始数
+fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+// 已经退出的容器，供 prune 使用
+pub fn exited_containers() -> Vec<String> {
+    list_metainfo().into_iter()
+        .filter(|metainfo| metainfo.status == "exited")
+        .map(|metainfo| metainfo.id)
+        .collect()
+}
+
+pub fn get_pid(container_id: &str) -> Result<u32> {
+    get_metainfo(container_id)?.pid
+        .ok_or_else(|| Error::Conflict(format!("Container {} is not running", container_id)))
+}
+
+pub fn is_running(container_id: &str) -> Result<bool> {
+    Ok(get_metainfo(container_id)?.status == "running")
+}
+
+pub fn get_command(container_id: &str) -> Result<RunCommand> {
+    Ok(get_metainfo(container_id)?.command)
+}
+
+pub fn get_endpoint(container_id: &str) -> Result<Option<Endpoint>> {
+    Ok(get_metainfo(container_id)?.endpoint)
+}
+
+// 没有记录 capability 的旧容器按照容器的命令重新计算
+pub fn get_capabilities(container_id: &str) -> Result<Vec<String>> {
+    let metainfo = get_metainfo(container_id)?;
+    match metainfo.capabilities {
+        Some(capabilities) => Ok(capabilities),
+        None => container_capabilities(&metainfo.command),
+    }
 }
 
 pub fn metainfo_exists(container_id: &str) -> bool {
-    let metainfo_file = format!("{}{}/config.json", METAINFO_BASE_PATH, container_id);
+    let metainfo_file = format!("{}{}/config.json", metainfo_base_path(), container_id);
     std::fs::metadata(metainfo_file).is_ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn container(name: &str, image: &str, status: &str) -> Metainfo {
+        Metainfo {
+            pid: None,
+            start_time: None,
+            id: "0123456789ab".to_string(),
+            command: RunCommand::try_parse_from(["run", "--name", name, image, "sleep"]).unwrap(),
+            status: status.to_string(),
+            created_at: Local::now(),
+            started_at: None,
+            finished_at: None,
+            exit_code: None,
+            signal: None,
+            oom_killed: false,
+            endpoint: None,
+            capabilities: None,
+            supervisor_pid: None,
+            supervisor_start_time: None,
+        }
+    }
+
+    fn filters(filters: &[&str]) -> BTreeMap<String, Vec<String>> {
+        parse_filters(&filters.iter().map(|filter| filter.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn groups_filters_by_key() {
+        let grouped = filters(&["status=exited", "name=web", "status=created", "name=a=b"]);
+        assert_eq!(grouped["status"], ["exited", "created"]);
+        assert_eq!(grouped["name"], ["web", "a=b"]);
+        for filter in ["status", "label=x", "=running"] {
+            assert!(parse_filters(&[filter.to_string()]).is_err(), "{:?} should be rejected", filter);
+        }
+    }
+
+    #[test]
+    fn ors_values_within_a_key_and_ands_across_keys() {
+        let exited = container("web-1", "busybox", "exited");
+        let created = container("db", "busybox", "created");
+        let running = container("web-2", "alpine", "running");
+
+        let status = filters(&["status=exited", "status=created"]);
+        assert!(matches_filters(&exited, &status));
+        assert!(matches_filters(&created, &status));
+        assert!(!matches_filters(&running, &status));
+
+        let web_exited = filters(&["status=exited", "status=running", "name=web"]);
+        assert!(matches_filters(&exited, &web_exited));
+        assert!(!matches_filters(&created, &web_exited));
+        assert!(matches_filters(&running, &web_exited));
+
+        let ancestor = filters(&["ancestor=alpine", "name=web"]);
+        assert!(!matches_filters(&exited, &ancestor));
+        assert!(matches_filters(&running, &ancestor));
+        assert!(matches_filters(&running, &BTreeMap::new()));
+    }
+}
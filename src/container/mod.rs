@@ -1,7 +1,29 @@
 pub mod init;
 pub mod overlayfs;
 pub mod metainfo;
+pub mod tty;
+pub mod console;
+pub mod sync;
+pub mod userns;
+pub mod hosts;
+pub mod image;
+pub mod env;
+pub mod user;
+pub mod capabilities;
+pub mod mounts;
+pub mod devices;
 
 pub use init::*;
 pub use overlayfs::*;
-pub use metainfo::*;
\ No newline at end of file
+pub use metainfo::*;
+pub use tty::*;
+pub use console::*;
+pub use sync::*;
+pub use userns::*;
+pub use hosts::*;
+pub use image::*;
+pub use env::*;
+pub use user::*;
+pub use capabilities::*;
+pub use mounts::*;
+pub use devices::*;
\ No newline at end of file
@@ -0,0 +1,208 @@
+use nix::mount::{mount, MsFlags};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use std::path::Path;
+
+use crate::RunCommand;
+use crate::error::{Context, Error, Result};
+
+// 与 docker 一样屏蔽的路径：目录用只读的空 tmpfs 覆盖，文件用 /dev/null 覆盖
+const MASKED_PATHS: [&str; 11] = [
+    "/proc/asound", "/proc/acpi", "/proc/kcore", "/proc/keys", "/proc/latency_stats", "/proc/timer_list",
+    "/proc/timer_stats", "/proc/sched_debug", "/proc/scsi", "/sys/firmware", "/sys/devices/virtual/powercap",
+];
+
+// 与 docker 一样设为只读的路径，容器中不能通过它们修改宿主机的内核参数
+const READONLY_PATHS: [&str; 5] = ["/proc/bus", "/proc/fs", "/proc/irq", "/proc/sys", "/proc/sysrq-trigger"];
+
+// docker 为 --tmpfs 设置的默认选项，可以被用户指定的选项覆盖
+const DEFAULT_TMPFS_OPTIONS: &str = "noexec,nosuid,nodev";
+
+// 在 pivot_root 之前挂载到容器根目录下的文件系统，target 为容器中的绝对路径
+pub struct MountSpec {
+    pub source: String,
+    pub target: String,
+    pub fstype: Option<String>,
+    pub flags: MsFlags,
+    pub data: Option<String>,
+}
+
+impl MountSpec {
+    fn new(source: &str, target: &str, fstype: Option<&str>, flags: MsFlags, data: Option<&str>) -> Self {
+        MountSpec {
+            source: source.to_string(),
+            target: target.to_string(),
+            fstype: fstype.map(str::to_string),
+            flags,
+            data: data.map(str::to_string),
+        }
+    }
+
+    // 挂载点不存在时创建，镜像中不一定有 /proc、/sys 这些目录
+    pub fn mount(&self, new_root: &Path) -> Result<()> {
+        let target = new_root.join(self.target.trim_start_matches('/'));
+        std::fs::create_dir_all(&target).context(format!("Failed to create mount point {}", self.target))?;
+        mount(Some(self.source.as_str()), &target, self.fstype.as_deref(), self.flags, self.data.as_deref())
+            .context(format!("Failed to mount {} on {}", self.source, self.target))
+    }
+}
+
+// 容器需要的文件系统，以及 --tmpfs 指定的 tmpfs
+pub fn container_mounts(command: &RunCommand, userns: bool) -> Result<Vec<MountSpec>> {
+    let hardened = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+    let readonly = if command.privileged { hardened } else { hardened | MsFlags::MS_RDONLY };
+    let mut mounts = vec![
+        MountSpec::new("proc", "/proc", Some("proc"), hardened, None),
+        // 只有 CAP_SYS_ADMIN 才能修改 sysfs 和 cgroup，普通容器中挂载为只读
+        MountSpec::new("sysfs", "/sys", Some("sysfs"), readonly, None),
+        // 在容器的 cgroup namespace 中挂载，根目录即容器自己的 cgroup，容器中的程序可以读到自己的资源限制
+        MountSpec::new("cgroup", "/sys/fs/cgroup", Some("cgroup2"), readonly, None),
+    ];
+    // 容器有自己的 /dev，其中的设备节点在挂载之后由 setup_devices 创建，不暴露宿主机上的其他设备
+    // user namespace 中 gid 5（tty 组）可能没有映射，不设置 devpts 的 gid
+    let devpts_options = if userns { "newinstance,ptmxmode=0666,mode=0620" } else { "newinstance,ptmxmode=0666,mode=0620,gid=5" };
+    mounts.extend([
+        MountSpec::new("tmpfs", "/dev", Some("tmpfs"), MsFlags::MS_NOSUID | MsFlags::MS_STRICTATIME, Some("mode=755,size=65536k")),
+        MountSpec::new("devpts", "/dev/pts", Some("devpts"), MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC, Some(devpts_options)),
+        MountSpec::new("shm", "/dev/shm", Some("tmpfs"), hardened, Some("mode=1777,size=65536k")),
+        MountSpec::new("mqueue", "/dev/mqueue", Some("mqueue"), hardened, None),
+    ]);
+    for tmpfs in &command.tmpfs {
+        mounts.push(parse_tmpfs(tmpfs)?);
+    }
+    Ok(mounts)
+}
+
+// --tmpfs 形如 /path[:opts]，opts 中的 ro、nosuid 等转换为挂载标志，size=、mode= 等交给 tmpfs
+fn parse_tmpfs(spec: &str) -> Result<MountSpec> {
+    let (target, options) = spec.split_once(':').unwrap_or((spec, ""));
+    if !target.starts_with('/') || target == "/" || target.split('/').any(|part| part == "..") {
+        return Err(Error::InvalidArgument(format!("Invalid tmpfs mount point {}, must be an absolute path other than /", target)));
+    }
+    let options = format!("{},{}", DEFAULT_TMPFS_OPTIONS, options);
+    let (flags, data) = parse_mount_options(&options);
+    Ok(MountSpec::new("tmpfs", target, Some("tmpfs"), flags, Some(&data)))
+}
+
+// 返回挂载标志和其余交给文件系统的选项，同一个标志后出现的覆盖先出现的
+fn parse_mount_options(options: &str) -> (MsFlags, String) {
+    let mut flags = MsFlags::empty();
+    let mut data = vec![];
+    for option in options.split(',').filter(|option| !option.is_empty()) {
+        let (flag, set) = match option {
+            "ro" => (MsFlags::MS_RDONLY, true),
+            "rw" => (MsFlags::MS_RDONLY, false),
+            "nosuid" => (MsFlags::MS_NOSUID, true),
+            "suid" => (MsFlags::MS_NOSUID, false),
+            "nodev" => (MsFlags::MS_NODEV, true),
+            "dev" => (MsFlags::MS_NODEV, false),
+            "noexec" => (MsFlags::MS_NOEXEC, true),
+            "exec" => (MsFlags::MS_NOEXEC, false),
+            "sync" => (MsFlags::MS_SYNCHRONOUS, true),
+            "async" => (MsFlags::MS_SYNCHRONOUS, false),
+            "noatime" => (MsFlags::MS_NOATIME, true),
+            "atime" => (MsFlags::MS_NOATIME, false),
+            "nodiratime" => (MsFlags::MS_NODIRATIME, true),
+            "diratime" => (MsFlags::MS_NODIRATIME, false),
+            "relatime" => (MsFlags::MS_RELATIME, true),
+            "norelatime" => (MsFlags::MS_RELATIME, false),
+            "strictatime" => (MsFlags::MS_STRICTATIME, true),
+            _ => {
+                data.push(option);
+                continue;
+            }
+        };
+        flags.set(flag, set);
+    }
+    (flags, data.join(","))
+}
+
+pub fn validate_mount_options(command: &RunCommand) -> Result<()> {
+    for tmpfs in &command.tmpfs {
+        parse_tmpfs(tmpfs)?;
+    }
+    Ok(())
+}
+
+// 在 pivot_root 之后调用，路径都是容器中的路径；不存在的路径直接跳过
+pub fn mask_paths() -> Result<()> {
+    for path in MASKED_PATHS {
+        let result = match std::fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => {
+                mount(Some("tmpfs"), path, Some("tmpfs"), MsFlags::MS_RDONLY, None::<&str>)
+            }
+            Ok(_) => mount(Some("/dev/null"), path, None::<&str>, MsFlags::MS_BIND, None::<&str>),
+            Err(_) => continue,
+        };
+        result.context(format!("Failed to mask {}", path))?;
+    }
+    Ok(())
+}
+
+pub fn readonly_paths() -> Result<()> {
+    for path in READONLY_PATHS {
+        if std::fs::symlink_metadata(path).is_err() {
+            continue;
+        }
+        // 先绑定挂载到自身成为独立的挂载点，才能单独设为只读
+        mount(Some(path), path, None::<&str>, MsFlags::MS_BIND | MsFlags::MS_REC, None::<&str>)
+            .context(format!("Failed to bind mount {}", path))?;
+        remount_readonly(path)?;
+    }
+    Ok(())
+}
+
+// 把绑定挂载的挂载点重新挂载为只读
+// user namespace 中不允许去掉 nosuid、nodev 等被锁定的标志，所以要带上挂载点原有的标志
+pub fn remount_readonly(path: &str) -> Result<()> {
+    let stat = statvfs(path).context(format!("Failed to stat {}", path))?;
+    let mut flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+    for (fs_flag, ms_flag) in [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        if stat.flags().contains(fs_flag) {
+            flags |= ms_flag;
+        }
+    }
+    mount(None::<&str>, path, None::<&str>, flags, None::<&str>)
+        .context(format!("Failed to remount {} read-only", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mount_options() {
+        let (flags, data) = parse_mount_options("ro,size=64m,,nosuid,mode=1777");
+        assert_eq!(flags, MsFlags::MS_RDONLY | MsFlags::MS_NOSUID);
+        assert_eq!(data, "size=64m,mode=1777");
+        // 同一个标志后出现的覆盖先出现的
+        let (flags, data) = parse_mount_options("noexec,nodev,exec,ro,rw");
+        assert_eq!(flags, MsFlags::MS_NODEV);
+        assert_eq!(data, "");
+    }
+
+    #[test]
+    fn tmpfs_defaults_can_be_overridden() {
+        let spec = parse_tmpfs("/run").unwrap();
+        assert_eq!((spec.source.as_str(), spec.target.as_str(), spec.fstype.as_deref()), ("tmpfs", "/run", Some("tmpfs")));
+        assert_eq!(spec.flags, MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV);
+        assert_eq!(spec.data.as_deref(), Some(""));
+
+        let spec = parse_tmpfs("/tmp:exec,size=1g").unwrap();
+        assert_eq!(spec.flags, MsFlags::MS_NOSUID | MsFlags::MS_NODEV);
+        assert_eq!(spec.data.as_deref(), Some("size=1g"));
+    }
+
+    #[test]
+    fn rejects_invalid_tmpfs_targets() {
+        for spec in ["", "run", "/", ":size=1m", "/a/../b", "/..:ro"] {
+            assert!(parse_tmpfs(spec).is_err(), "{:?} should be rejected", spec);
+        }
+    }
+}
@@ -1,126 +1,301 @@
-use log::info;
-use std::path::PathBuf;
+use log::{error, info};
+use nix::errno::Errno;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 use std::fs::*;
 use std::process::Command;
 
-use crate::run::{IMAGE_BASE_PATH, ROOTFS_BASE_PATH};
+use crate::container::{shift_ownership, UserNamespace};
+use crate::state::{image_base_path, is_rootless, rootfs_base_path};
+use crate::error::{Context, Error, Result};
+use crate::rollback::Rollback;
+
+// rootless 模式下不能在宿主机上 mount，这些挂载由容器的 init 进程在自己的 mount namespace 中完成
+#[derive(Default, Clone)]
+pub struct PendingMounts {
+    pub overlay: Option<String>,                // 没有 fuse-overlayfs 时使用内核的非特权 overlay，值为 mount 选项
+    pub volume: Option<(PathBuf, PathBuf)>,     // 宿主机上的目录和它在容器中的挂载点
+}
 
 // 这里在确定 root 参数类型时从 String、&String 和 &str 中选择了 &str
 // String 涉及所有权的转移
 // &String 的引用不如 &str 灵活，比如 &str 能接收 "abc" 这样的字符串字面量，而 &String 不能
 // &str 还能接收 String 的引用，会自动调用 deref 进行转换
-pub fn new_workspace(container_id: &str, image: &str, volumn: Option<&str>) {
-    info!("Creating overlayfs workspace at {}{}", ROOTFS_BASE_PATH, container_id);
+// 每完成一步都在 rollback 中登记撤销操作，后续步骤失败时由调用者统一撤销
+// 返回需要由容器 init 进程完成的挂载，只有 rootless 模式下才不为空
+pub fn new_workspace<'a>(
+    container_id: &'a str,
+    image: &str,
+    volumn: Option<&str>,
+    userns: Option<&UserNamespace>,
+    rollback: &mut Rollback<'a>,
+) -> Result<PendingMounts> {
+    info!("Creating overlayfs workspace at {}{}", rootfs_base_path(), container_id);
+    // 先检查参数，避免创建了一半的工作空间
+    let volume = volumn.map(parse_volume).transpose()?;
+    if let Some((volume, _)) = &volume
+        && !volume.is_dir()
+    {
+        return Err(Error::InvalidArgument(format!("Volume {} does not exist or is not a directory", volume.display())));
+    }
+
     info!("Create some directories and mount overlayfs to merged.");
-    create_lower(container_id, image);
-    create_others(container_id);
-    mount_overlayfs(container_id, image);
+    // lower 层是解压后的镜像，保留下来供容器重新启动时使用，不需要撤销
+    create_lower(container_id, image, userns)?;
+    create_others(container_id, userns)?;
+    rollback.push("overlayfs directories", move || {
+        remove_others(container_id);
+        Ok(())
+    });
 
-    if let Some(volumn) = volumn {
-        info!("Mounting volume {}", volumn);
-        let (volume, mount_point) = parse_volume(volumn);
-        let mount_point = PathBuf::from(format!("{}{}/merged{}", ROOTFS_BASE_PATH, container_id, mount_point.display()));
-        if !mount_point.exists() {
-            panic!("Mount point {} does not exist", mount_point.display());
-        }
-        if !mount_point.is_dir() {
-            panic!("Mount point {} is not a directory", mount_point.display());
-        }
-        // 绑定挂载
-        nix::mount::mount(
-            Some(&volume),
-            &mount_point,
-            None::<&str>,
-            nix::mount::MsFlags::MS_BIND,
-            None::<&str>,
-        ).expect("Bind mount failed");
+    let mut pending = PendingMounts::default();
+    let layers = overlay_layers(container_id, image);
+    if !is_rootless() {
+        mount_overlayfs(&layers)?;
+    } else if has_fuse_overlayfs() {
+        mount_fuse_overlayfs(&layers)?;
+    } else {
+        info!("fuse-overlayfs not found, overlayfs will be mounted in the container's user namespace");
+        pending.overlay = Some(overlay_options(&layers) + ",userxattr");
+    }
+    if pending.overlay.is_none() {
+        let merged = layers.merged;
+        rollback.push(format!("overlayfs mount {}", merged), move || umount(Path::new(&merged)));
+    }
 
-        info!("Successfully mounted {} to {}", volume.display(), mount_point.display());
+    if let Some((volume, mount_point)) = volume {
+        if is_rootless() {
+            pending.volume = Some((volume, mount_point));
+        } else {
+            let mount_point = mount_volume(container_id, &volume, &mount_point)?;
+            rollback.push(format!("volume mount {}", mount_point.display()), move || umount(&mount_point));
+        }
     }
+    Ok(pending)
 }
 
-fn parse_volume(volume: &str) -> (PathBuf, PathBuf) {
-    let (volume, mount_point) = volume.split_once(':').expect("Invalid volume format");
-    let volume = PathBuf::from(volume);
-    let mount_point = PathBuf::from(mount_point);
-
-    if !volume.exists() {
-        panic!("Volume {} does not exist", volume.display());
+// 返回 volume 在宿主机上的挂载点
+fn mount_volume(container_id: &str, volume: &Path, mount_point: &Path) -> Result<PathBuf> {
+    info!("Mounting volume {} to {}", volume.display(), mount_point.display());
+    let mount_point = volume_mount_point(container_id, mount_point);
+    if !mount_point.is_dir() {
+        return Err(Error::InvalidArgument(
+            format!("Mount point {} does not exist or is not a directory", mount_point.display())
+        ));
     }
+    // 绑定挂载
+    nix::mount::mount(
+        Some(volume),
+        &mount_point,
+        None::<&str>,
+        nix::mount::MsFlags::MS_BIND,
+        None::<&str>,
+    ).context(format!("Failed to bind mount {} to {}", volume.display(), mount_point.display()))?;
 
-    if !volume.is_dir() {
-        panic!("Volume {} is not a directory", volume.display());
+    info!("Successfully mounted {} to {}", volume.display(), mount_point.display());
+    Ok(mount_point)
+}
+
+// volume 形如 /host/dir:/container/dir，只做语法上的解析，不检查目录是否存在
+fn parse_volume(volume: &str) -> Result<(PathBuf, PathBuf)> {
+    match volume.split_once(':') {
+        Some((volume, mount_point)) if !volume.is_empty() && mount_point.starts_with('/') => {
+            Ok((PathBuf::from(volume), PathBuf::from(mount_point)))
+        }
+        _ => Err(Error::InvalidArgument(
+            format!("Invalid volume format {}, expected /host/dir:/container/dir", volume)
+        )),
     }
-    return (volume, mount_point);
 }
 
-fn create_lower(container_id: &str, image: &str) {
-    let overlayfs = format!("{}{}/{}", ROOTFS_BASE_PATH, container_id, image);
-    let image = IMAGE_BASE_PATH.to_string() + image + ".tar";
+fn volume_mount_point(container_id: &str, mount_point: &Path) -> PathBuf {
+    PathBuf::from(format!("{}{}/merged{}", rootfs_base_path(), container_id, mount_point.display()))
+}
+
+fn create_lower(container_id: &str, image: &str, userns: Option<&UserNamespace>) -> Result<()> {
+    let overlayfs = format!("{}{}/{}", rootfs_base_path(), container_id, image);
+    let image_tar = image_base_path() + image + ".tar";
 
-    if let Err(_) = exists(&image) {
-        panic!("cannot find image {}", image);
+    if !exists(&image_tar).context(format!("Failed to check image {}", image_tar))? {
+        return Err(Error::NoSuchImage(image.to_string()));
     }
-    
-    match exists(&overlayfs) {
-        Ok(exists) => {
-            if exists {
-                info!("Overlayfs {} already exists", overlayfs);
-            } else {
-                info!("Use image {} to create overlayfs {}", image, overlayfs);
-                create_dir_all(&overlayfs).expect("Failed to create directory");
-                Command::new("tar")
-                    .args(&["-xf", &image, "-C", &overlayfs])
-                    .status()
-                    .expect("failed to execute tar command");
-            }
-        }
-        Err(_) => {
-            panic!("Error checking image existence");
-        }
+
+    if exists(&overlayfs).context(format!("Failed to check {}", overlayfs))? {
+        info!("Overlayfs {} already exists", overlayfs);
+        return Ok(());
+    }
+
+    info!("Use image {} to create overlayfs {}", image_tar, overlayfs);
+    create_dir_all(&overlayfs).context(format!("Failed to create directory {}", overlayfs))?;
+    let status = Command::new("tar")
+        .args(["-xf", &image_tar, "-C", &overlayfs])
+        .status()
+        .context("Failed to execute tar command")?;
+    if !status.success() {
+        // 解压了一半的镜像不能留给下次使用
+        let _ = remove_dir_all(&overlayfs);
+        return Err(Error::Runtime(format!("Failed to extract image {}: tar {}", image_tar, status)));
+    }
+    // rootless 模式下 tar 解压出的文件都属于当前用户，本来就映射为容器中的 root
+    if let Some(userns) = userns
+        && !is_rootless()
+        && let Err(e) = shift_ownership(Path::new(&overlayfs), userns)
+    {
+        let _ = remove_dir_all(&overlayfs);
+        return Err(e);
     }
+    Ok(())
 }
 
 // create upper & work
-fn create_others(container_id: &str) {
+fn create_others(container_id: &str, userns: Option<&UserNamespace>) -> Result<()> {
     let others = vec!["upper", "work", "merged"];
     for dir in others {
-        let dir_path = format!("{}{}/{}", ROOTFS_BASE_PATH, container_id, dir);
+        let dir_path = format!("{}{}/{}", rootfs_base_path(), container_id, dir);
         if !PathBuf::from(&dir_path).exists() { // 检查目录是否存在的方法还挺多
-            create_dir_all(&dir_path).expect("Failed to create directory");
+            create_dir_all(&dir_path).context(format!("Failed to create directory {}", dir_path))?;
+        }
+        // upper 的属主决定了容器中根目录的属主
+        if let Some(userns) = userns {
+            let (uid, gid) = userns.root_owner();
+            std::os::unix::fs::chown(&dir_path, Some(uid), Some(gid)).context(format!("Failed to chown {}", dir_path))?;
         }
     }
+    Ok(())
 }
 
-fn mount_overlayfs(container_id: &str, image: &str) {
+fn remove_others(container_id: &str) {
+    for dir in ["upper", "work", "merged"] {
+        let dir_path = format!("{}{}/{}", rootfs_base_path(), container_id, dir);
+        if is_rootless() {
+            make_removable(Path::new(&dir_path));
+        }
+        if let Err(e) = remove_dir_all(&dir_path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            error!("Failed to remove directory {}: {}", dir_path, e);
+        }
+    }
+}
+
+// 容器中的 overlayfs 会在 work 下创建权限为 000 的目录，普通用户要先给自己加上权限才能删除
+fn make_removable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    let Ok(metadata) = symlink_metadata(path) else {
+        return;
+    };
+    if !metadata.is_dir() {
+        return;
+    }
+    let _ = set_permissions(path, Permissions::from_mode(0o700));
+    if let Ok(entries) = read_dir(path) {
+        for entry in entries.flatten() {
+            make_removable(&entry.path());
+        }
+    }
+}
+
+// 容器 overlayfs 各层所在的目录
+#[derive(Serialize)]
+pub struct OverlayLayers {
+    pub lower: String,
+    pub upper: String,
+    pub work: String,
+    pub merged: String,
+}
+
+pub fn overlay_layers(container_id: &str, image: &str) -> OverlayLayers {
+    let root = format!("{}{}", rootfs_base_path(), container_id);
+    OverlayLayers {
+        lower: format!("{}/{}", root, image),
+        upper: format!("{}/upper", root),
+        work: format!("{}/work", root),
+        merged: format!("{}/merged", root),
+    }
+}
+
+fn overlay_options(layers: &OverlayLayers) -> String {
+    format!("lowerdir={},upperdir={},workdir={}", layers.lower, layers.upper, layers.work)
+}
+
+fn mount_overlayfs(layers: &OverlayLayers) -> Result<()> {
     // 完整命令：mount -t overlay overlay -o lowerdir=/root/busybox,upperdir=/root/upper,workdir=/root/work /root/merged
-    let root = format!("{}{}", ROOTFS_BASE_PATH, container_id);
-    let lower = format!("{}/{}", root, image);
-    let upper = format!("{}/upper", root);
-    let work = format!("{}/work", root);
-    let merged = format!("{}/merged", root);
-    let options = format!("lowerdir={},upperdir={},workdir={}", lower, upper, work);
-    Command::new("mount")
-        .args(&["-t", "overlay", "overlay", "-o", &options, &merged])
+    let options = overlay_options(layers);
+    let status = Command::new("mount")
+        .args(["-t", "overlay", "overlay", "-o", &options, &layers.merged])
         .status()
-        .expect("failed to execute mount command");
+        .context("Failed to execute mount command")?;
+    if !status.success() {
+        return Err(Error::Runtime(format!("Failed to mount overlayfs at {}: mount {}", layers.merged, status)));
+    }
+    Ok(())
 }
 
-pub fn delete_workspace(container_id: &str, volumn: Option<&str>) {
+fn has_fuse_overlayfs() -> bool {
+    Command::new("fuse-overlayfs").arg("--version").output().is_ok_and(|output| output.status.success())
+}
+
+// 普通用户在宿主机上挂载 overlayfs 只能借助 FUSE，挂载后 commit 等命令可以直接读取 merged
+fn mount_fuse_overlayfs(layers: &OverlayLayers) -> Result<()> {
+    let status = Command::new("fuse-overlayfs")
+        .args(["-o", &overlay_options(layers), &layers.merged])
+        .status()
+        .context("Failed to execute fuse-overlayfs command")?;
+    if !status.success() {
+        return Err(Error::Runtime(format!("Failed to mount overlayfs at {}: fuse-overlayfs {}", layers.merged, status)));
+    }
+    Ok(())
+}
+
+// 尽量清理干净：某一步失败时记录下来继续清理后面的部分，最后返回第一个错误
+pub fn delete_workspace(container_id: &str, volumn: Option<&str>) -> Result<()> {
+    let mut result = Ok(());
     if let Some(volumn) = volumn {
         info!("Unmount bind volume {}", volumn);
-        let (.., mount_point) = parse_volume(volumn);
-        let mount_point = PathBuf::from(format!("{}{}/merged{}", ROOTFS_BASE_PATH, container_id, mount_point.display()));
-        nix::mount::umount(&mount_point).expect("Unmount bind volume failed");
+        let (.., mount_point) = parse_volume(volumn)?;
+        let mount_point = volume_mount_point(container_id, &mount_point);
+        result = umount(&mount_point);
     }
-    
-    let root = format!("{}{}", ROOTFS_BASE_PATH, container_id);
+
+    let root = format!("{}{}", rootfs_base_path(), container_id);
     info!("Deleting overlayfs workspace at {}", root);
     let merged = PathBuf::from(format!("{}/merged", root));
-    nix::mount::umount(&merged).expect("Failed to unmount overlayfs");
-    let others = vec!["upper", "work", "merged"];
-    for dir in others {
-        let dir_path = format!("{}/{}", root, dir);
-        std::fs::remove_dir_all(dir_path).expect("Failed to remove directory");
+    let unmounted = umount(&merged);
+    if unmounted.is_ok() {
+        remove_others(container_id);
+    }
+    result.and(unmounted)
+}
+
+// 没有挂载（EINVAL）或者目录已经不在（ENOENT）时视为已经卸载
+// rootless 模式下宿主机上只有 fuse-overlayfs 的挂载，普通用户只能通过 fusermount 卸载
+fn umount(path: &Path) -> Result<()> {
+    if is_rootless() {
+        if !is_mounted(path) {
+            return Ok(());
+        }
+        let output = Command::new("fusermount3").arg("-u").arg(path).output()
+            .or_else(|_| Command::new("fusermount").arg("-u").arg(path).output())
+            .context("Failed to execute fusermount command")?;
+        if !output.status.success() {
+            return Err(Error::Runtime(format!(
+                "Failed to unmount {}: {}", path.display(), String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        return Ok(());
     }
+    match nix::mount::umount(path) {
+        Ok(()) | Err(Errno::EINVAL) | Err(Errno::ENOENT) => Ok(()),
+        Err(e) => Err(e).context(format!("Failed to unmount {}", path.display())),
+    }
+}
+
+// /proc/self/mountinfo 的第 5 列是挂载点
+fn is_mounted(path: &Path) -> bool {
+    let Ok(mountinfo) = read_to_string("/proc/self/mountinfo") else {
+        return false;
+    };
+    mountinfo.lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .any(|mount_point| Path::new(mount_point) == path)
 }
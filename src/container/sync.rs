@@ -0,0 +1,120 @@
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+use nix::errno::Errno;
+
+use crate::error::{Context, Error, Result};
+
+// 父进程通知 init 进程继续执行的字节
+const RESUME: u8 = 0;
+
+// 启动结果在进程间传递的格式：1 字节类型 + 负载
+// execvp 失败时负载为 4 字节 errno（大端）+ 命令，其他错误的负载为错误信息
+const RESULT_OK: u8 = 0;
+const RESULT_ERROR: u8 = 1;
+const RESULT_EXEC: u8 = 2;
+
+pub fn encode_result(result: &Result<()>) -> Vec<u8> {
+    match result {
+        Ok(()) => vec![RESULT_OK],
+        Err(Error::Exec { command, errno }) => {
+            let mut buf = vec![RESULT_EXEC];
+            buf.extend_from_slice(&(*errno as i32).to_be_bytes());
+            buf.extend_from_slice(command.as_bytes());
+            buf
+        }
+        Err(e) => {
+            let mut buf = vec![RESULT_ERROR];
+            buf.extend_from_slice(e.to_string().as_bytes());
+            buf
+        }
+    }
+}
+
+pub fn decode_result(buf: &[u8]) -> Result<()> {
+    match buf.split_first() {
+        Some((&RESULT_OK, [])) => Ok(()),
+        Some((&RESULT_EXEC, payload)) if payload.len() >= 4 => {
+            let errno = i32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+            Err(Error::exec(&String::from_utf8_lossy(&payload[4..]), Errno::from_raw(errno)))
+        }
+        Some((&RESULT_ERROR, message)) => Err(Error::Runtime(String::from_utf8_lossy(message).to_string())),
+        _ => Err(Error::Runtime("Received a malformed result".to_string())),
+    }
+}
+
+// 父进程与容器 init 进程之间的同步通道：
+// init 进程阻塞到父进程完成 cgroup、网络等配置之后才开始 mount 和 execvp，
+// mount 或 execvp 失败时把错误（包括 execvp 的 errno）写回父进程；execvp 成功后 fd 随 O_CLOEXEC 关闭，父进程读到 EOF
+pub struct SyncPipe {
+    parent: UnixStream,
+    child: UnixStream,
+}
+
+impl SyncPipe {
+    pub fn new() -> Result<Self> {
+        // UnixStream::pair 创建的两端都带有 O_CLOEXEC
+        let (parent, child) = UnixStream::pair().context("Failed to create sync socket")?;
+        Ok(SyncPipe { parent, child })
+    }
+
+    pub fn child_end(&self) -> ChildSync {
+        ChildSync { fd: self.child.as_raw_fd(), peer: self.parent.as_raw_fd() }
+    }
+
+    // clone 之后调用，关闭父进程中 init 进程一侧的 fd，否则 init 进程 execvp 之后父进程读不到 EOF
+    pub fn into_parent(self) -> ParentSync {
+        drop(self.child);
+        ParentSync { stream: self.parent }
+    }
+}
+
+pub struct ParentSync {
+    stream: UnixStream,
+}
+
+impl ParentSync {
+    // 配置完成，允许 init 进程继续执行
+    pub fn resume(&mut self) -> Result<()> {
+        self.stream.write_all(&[RESUME]).context("Failed to resume container init process")
+    }
+
+    // 等待 init 进程 execvp，返回 init 进程报告的错误
+    pub fn wait_exec(mut self) -> Result<()> {
+        let mut buf = vec![];
+        self.stream.read_to_end(&mut buf).context("Failed to wait for container init process")?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        decode_result(&buf)
+    }
+}
+
+// init 进程一侧使用的 fd，随 RunArg 传入 init 进程
+#[derive(Clone, Copy)]
+pub struct ChildSync {
+    fd: RawFd,
+    peer: RawFd,    // 父进程一侧的 fd，init 进程中要先关闭
+}
+
+impl ChildSync {
+    // 阻塞到父进程发来 RESUME；父进程出错退出时读到 EOF
+    pub fn wait_resume(&self) -> Result<()> {
+        let _ = nix::unistd::close(self.peer);
+        let mut buf = [0u8; 1];
+        loop {
+            match nix::unistd::read(self.fd, &mut buf) {
+                Ok(1) if buf[0] == RESUME => return Ok(()),
+                Ok(_) => return Err(Error::Runtime("Parent process exited before the container was set up".to_string())),
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(e).context("Failed to wait for parent process"),
+            }
+        }
+    }
+
+    pub fn report(&self, error: Error) {
+        let fd = unsafe { BorrowedFd::borrow_raw(self.fd) };
+        let _ = nix::unistd::write(fd, &encode_result(&Err(error)));
+    }
+}
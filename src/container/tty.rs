@@ -0,0 +1,186 @@
+use std::io::{Read, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{error, info};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::pty::{openpty, Winsize};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, Termios};
+use nix::unistd::{dup2, isatty, setsid};
+
+use crate::error::{Context, Result};
+
+// 收到 SIGWINCH 时置位，由 proxy 循环负责把新的窗口大小同步给容器的终端
+static WINDOW_CHANGED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigwinch(_: libc::c_int) {
+    WINDOW_CHANGED.store(true, Ordering::SeqCst);
+}
+
+pub fn watch_window_size() {
+    let handler = SigAction::new(SigHandler::Handler(handle_sigwinch), SaFlags::empty(), SigSet::empty());
+    unsafe {
+        if let Err(e) = sigaction(Signal::SIGWINCH, &handler) {
+            error!("Failed to install SIGWINCH handler: {}", e);
+        }
+    }
+}
+
+pub fn unwatch_window_size() {
+    let default = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+    unsafe {
+        let _ = sigaction(Signal::SIGWINCH, &default);
+    }
+}
+
+// 自上次调用以来用户终端的窗口大小是否发生过变化
+pub fn window_changed() -> bool {
+    WINDOW_CHANGED.swap(false, Ordering::SeqCst)
+}
+
+// 为容器分配一对 pty，master 留在宿主机一侧，slave 交给容器作为控制终端
+// master 设置 FD_CLOEXEC，避免容器进程 execvp 之后还持有它
+pub fn open_pty() -> nix::Result<(OwnedFd, OwnedFd)> {
+    let stdin = std::io::stdin();
+    let winsize = get_winsize(stdin.as_fd());
+    let pty = openpty(winsize.as_ref(), None)?;
+    fcntl(pty.master.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    Ok((pty.master, pty.slave))
+}
+
+// 在容器的 init 进程中调用：创建新会话，把 pty slave 设为控制终端并作为标准输入输出
+pub fn setup_controlling_terminal(slave: RawFd) -> nix::Result<()> {
+    setsid()?;
+    unsafe {
+        if libc::ioctl(slave, libc::TIOCSCTTY, 0) < 0 {
+            return Err(Errno::last());
+        }
+    }
+    for fd in 0..3 {
+        dup2(slave, fd)?;
+    }
+    if slave > 2 {
+        nix::unistd::close(slave)?;
+    }
+    Ok(())
+}
+
+pub fn get_winsize(fd: BorrowedFd) -> Option<Winsize> {
+    if !isatty(fd.as_raw_fd()).unwrap_or(false) {
+        return None;
+    }
+    let mut ws: Winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(fd.as_raw_fd(), libc::TIOCGWINSZ, &mut ws) };
+    if ret < 0 {
+        return None;
+    }
+    Some(ws)
+}
+
+pub fn set_winsize(fd: BorrowedFd, ws: &Winsize) {
+    unsafe {
+        libc::ioctl(fd.as_raw_fd(), libc::TIOCSWINSZ, ws);
+    }
+}
+
+// 将用户终端切换到 raw 模式，drop 时恢复原来的设置
+pub struct RawTerminal {
+    original: Option<Termios>,
+}
+
+impl RawTerminal {
+    pub fn new() -> Self {
+        let stdin = std::io::stdin();
+        if !isatty(stdin.as_raw_fd()).unwrap_or(false) {
+            return RawTerminal { original: None };
+        }
+        let original = match tcgetattr(stdin.as_fd()) {
+            Ok(termios) => termios,
+            Err(e) => {
+                error!("Failed to get terminal attributes: {}", e);
+                return RawTerminal { original: None };
+            }
+        };
+        let mut raw = original.clone();
+        cfmakeraw(&mut raw);
+        if let Err(e) = tcsetattr(stdin.as_fd(), SetArg::TCSANOW, &raw) {
+            error!("Failed to set terminal to raw mode: {}", e);
+            return RawTerminal { original: None };
+        }
+        RawTerminal { original: Some(original) }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        if let Some(original) = &self.original {
+            let _ = tcsetattr(std::io::stdin().as_fd(), SetArg::TCSANOW, original);
+        }
+    }
+}
+
+// 在前台运行的容器分配了 pty 时，由父进程调用：
+// 用户的输入写入 master，master 的输出写到用户终端，直到容器一侧关闭 slave
+pub fn proxy_pty(master: &OwnedFd, interactive: bool) -> Result<()> {
+    let mut master_file = std::fs::File::from(master.try_clone().context("Failed to dup pty master")?);
+    watch_window_size();
+    let _raw = if interactive { Some(RawTerminal::new()) } else { None };
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    let mut stdin_open = interactive;
+    let mut buf = [0u8; 4096];
+
+    info!("Proxying pty master to the current terminal");
+    loop {
+        if window_changed()
+            && let Some(ws) = get_winsize(stdin.as_fd())
+        {
+            set_winsize(master.as_fd(), &ws);
+        }
+
+        let mut fds = vec![PollFd::new(master.as_fd(), PollFlags::POLLIN)];
+        if stdin_open {
+            fds.push(PollFd::new(stdin.as_fd(), PollFlags::POLLIN));
+        }
+        match poll(&mut fds, PollTimeout::NONE) {
+            Ok(_) => {},
+            Err(Errno::EINTR) => continue,
+            Err(e) => {
+                error!("poll failed: {}", e);
+                break;
+            }
+        }
+        let master_events = fds[0].revents().unwrap_or(PollFlags::empty());
+        let stdin_events = if stdin_open { fds[1].revents().unwrap_or(PollFlags::empty()) } else { PollFlags::empty() };
+
+        if stdin_events.intersects(PollFlags::POLLIN | PollFlags::POLLHUP) {
+            // 直接读 fd，绕过 Stdin 自带的缓冲区，否则缓冲区中残留的数据不会再触发 poll
+            match nix::unistd::read(stdin.as_raw_fd(), &mut buf) {
+                Err(Errno::EINTR) => {},
+                Ok(0) | Err(_) => stdin_open = false,
+                Ok(n) => {
+                    if master_file.write_all(&buf[..n]).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if master_events.intersects(PollFlags::POLLIN | PollFlags::POLLHUP | PollFlags::POLLERR) {
+            // 容器内所有持有 slave 的进程都退出后，读 master 会返回 EIO
+            match master_file.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let _ = stdout.write_all(&buf[..n]);
+                    let _ = stdout.flush();
+                }
+            }
+        }
+    }
+
+    unwatch_window_size();
+    Ok(())
+}
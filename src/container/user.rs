@@ -0,0 +1,193 @@
+use log::warn;
+use nix::errno::Errno;
+use nix::unistd::{setgroups, setresgid, setresuid, Gid, Uid};
+
+use crate::error::{Context, Error, Result};
+
+// 容器进程最终的身份，由 -u 在容器自己的 /etc/passwd 和 /etc/group 中解析得到
+pub struct ExecUser {
+    pub uid: Uid,
+    pub gid: Gid,
+    pub groups: Vec<Gid>,   // 包括主组在内的附加组
+    pub home: String,
+}
+
+// /etc/passwd 的一行：name:password:uid:gid:gecos:home:shell
+struct PasswdEntry {
+    name: String,
+    uid: u32,
+    gid: u32,
+    home: String,
+}
+
+// /etc/group 的一行：name:password:gid:user1,user2
+struct GroupEntry {
+    name: String,
+    gid: u32,
+    members: Vec<String>,
+}
+
+// 在 pivot_root 之后调用，读取的是容器中的文件
+// user 和 group 都可以是名字或数字；数字的 uid 在 /etc/passwd 中不存在时与 docker 一样使用 gid 0
+pub fn resolve_user(spec: &str) -> Result<ExecUser> {
+    lookup_user(spec, "/etc/passwd", "/etc/group")
+}
+
+fn lookup_user(spec: &str, passwd_file: &str, group_file: &str) -> Result<ExecUser> {
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+    if user.is_empty() || group.is_some_and(str::is_empty) {
+        return Err(Error::InvalidArgument(format!("Invalid user {}, expected user[:group]", spec)));
+    }
+
+    let passwd = read_passwd(passwd_file)?;
+    let groups = read_group(group_file)?;
+    let entry = passwd.iter().find(|entry| entry.name == user || user.parse() == Ok(entry.uid));
+    let (uid, primary_gid, name, home) = match (entry, user.parse::<u32>()) {
+        (Some(entry), _) => (entry.uid, entry.gid, Some(entry.name.as_str()), entry.home.clone()),
+        (None, Ok(uid)) => (uid, 0, None, "/".to_string()),
+        (None, Err(_)) => {
+            return Err(Error::InvalidArgument(format!("Unable to find user {}: no matching entries in passwd file", user)));
+        }
+    };
+
+    let gid = match group {
+        None => primary_gid,
+        Some(group) => match groups.iter().find(|entry| entry.name == group || group.parse() == Ok(entry.gid)) {
+            Some(entry) => entry.gid,
+            None => group.parse::<u32>().map_err(|_| Error::InvalidArgument(
+                format!("Unable to find group {}: no matching entries in group file", group)
+            ))?,
+        },
+    };
+
+    let mut supplementary = vec![Gid::from_raw(gid)];
+    if let Some(name) = name {
+        supplementary.extend(groups.iter()
+            .filter(|entry| entry.gid != gid && entry.members.iter().any(|member| member == name))
+            .map(|entry| Gid::from_raw(entry.gid)));
+    }
+    Ok(ExecUser { uid: Uid::from_raw(uid), gid: Gid::from_raw(gid), groups: supplementary, home })
+}
+
+fn read_passwd(path: &str) -> Result<Vec<PasswdEntry>> {
+    Ok(read_entries(path)?.into_iter()
+        .filter_map(|fields| Some(PasswdEntry {
+            name: fields.first()?.clone(),
+            uid: fields.get(2)?.parse().ok()?,
+            gid: fields.get(3)?.parse().ok()?,
+            home: fields.get(5).filter(|home| !home.is_empty()).cloned().unwrap_or_else(|| "/".to_string()),
+        }))
+        .collect())
+}
+
+fn read_group(path: &str) -> Result<Vec<GroupEntry>> {
+    Ok(read_entries(path)?.into_iter()
+        .filter_map(|fields| Some(GroupEntry {
+            name: fields.first()?.clone(),
+            gid: fields.get(2)?.parse().ok()?,
+            members: fields.get(3)
+                .map(|members| members.split(',').filter(|m| !m.is_empty()).map(str::to_string).collect())
+                .unwrap_or_default(),
+        }))
+        .collect())
+}
+
+// 镜像中没有这个文件时视为空文件，只能使用数字的 uid 和 gid
+fn read_entries(path: &str) -> Result<Vec<Vec<String>>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).context(format!("Failed to read {}", path)),
+    };
+    Ok(content.lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.split(':').map(str::to_string).collect())
+        .collect())
+}
+
+// 先设置附加组和 gid，最后切换 uid，切换之后就没有权限再修改组了
+pub fn switch_user(user: &ExecUser) -> Result<()> {
+    match setgroups(&user.groups) {
+        Ok(()) => {}
+        // rootless 模式下没有 subgid 时 setgroups 被禁用
+        Err(Errno::EPERM) => warn!("setgroups is not permitted, supplementary groups are not set"),
+        Err(e) => return Err(e).context("setgroups failed"),
+    }
+    setresgid(user.gid, user.gid, user.gid).context(format!("Failed to switch to gid {}", user.gid))?;
+    setresuid(user.uid, user.uid, user.uid).context(format!("Failed to switch to uid {}", user.uid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "\
+root:x:0:0:root:/root:/bin/sh
+# comment
+nobody:x:65534:65534:nobody:/nonexistent:/usr/sbin/nologin
+web:x:1000:1000::/home/web:/bin/sh
+broken:x:abc:1:::
+";
+    const GROUP: &str = "\
+root:x:0:
+users:x:100:web,nobody
+web:x:1000:
+docker:x:999:web
+";
+
+    // 在临时目录中写好 passwd 和 group 文件后解析
+    fn lookup(spec: &str) -> Result<ExecUser> {
+        let dir = std::env::temp_dir().join(format!("mydocker-user-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let passwd = dir.join("passwd");
+        let group = dir.join("group");
+        std::fs::write(&passwd, PASSWD).unwrap();
+        std::fs::write(&group, GROUP).unwrap();
+        lookup_user(spec, passwd.to_str().unwrap(), group.to_str().unwrap())
+    }
+
+    fn gids(user: &ExecUser) -> Vec<u32> {
+        user.groups.iter().map(|gid| gid.as_raw()).collect()
+    }
+
+    #[test]
+    fn resolves_names_and_numbers() {
+        let user = lookup("web").unwrap();
+        assert_eq!((user.uid.as_raw(), user.gid.as_raw(), user.home.as_str()), (1000, 1000, "/home/web"));
+        assert_eq!(gids(&user), [1000, 100, 999]);
+
+        let user = lookup("1000").unwrap();
+        assert_eq!((user.uid.as_raw(), user.gid.as_raw()), (1000, 1000));
+
+        let user = lookup("web:docker").unwrap();
+        assert_eq!(user.gid.as_raw(), 999);
+        assert_eq!(gids(&user), [999, 100]);
+    }
+
+    #[test]
+    fn unknown_numeric_ids_are_used_as_is() {
+        // 与 docker 一样，不在 passwd 中的 uid 使用 gid 0 和 HOME=/
+        let user = lookup("4242").unwrap();
+        assert_eq!((user.uid.as_raw(), user.gid.as_raw(), user.home.as_str()), (4242, 0, "/"));
+        assert_eq!(gids(&user), [0]);
+        let user = lookup("4242:4343").unwrap();
+        assert_eq!(user.gid.as_raw(), 4343);
+    }
+
+    #[test]
+    fn rejects_unknown_or_invalid_users() {
+        for spec in ["", ":", "web:", ":users", "ghost", "web:ghosts", "broken"] {
+            assert!(lookup(spec).is_err(), "{:?} should be rejected", spec);
+        }
+    }
+
+    #[test]
+    fn missing_files_allow_numeric_ids() {
+        let user = lookup_user("7:8", "/nonexistent/passwd", "/nonexistent/group").unwrap();
+        assert_eq!((user.uid.as_raw(), user.gid.as_raw()), (7, 8));
+        assert!(lookup_user("root", "/nonexistent/passwd", "/nonexistent/group").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use std::os::unix::fs::{lchown, MetadataExt};
+use std::path::Path;
+use std::process::Command;
+
+use log::info;
+use nix::errno::Errno;
+use nix::unistd::{getegid, geteuid, setgroups, setresgid, setresuid, Gid, Pid, Uid, User};
+
+use crate::error::{Context, Error, Result};
+use crate::state::is_rootless;
+
+const SUBUID_FILE: &str = "/etc/subuid";
+const SUBGID_FILE: &str = "/etc/subgid";
+
+// 一段 ID 映射：容器中的 [container_id, container_id + size) 对应宿主机的 [host_id, host_id + size)
+#[derive(Clone, Copy, Debug)]
+pub struct IdMap {
+    pub container_id: u32,
+    pub host_id: u32,
+    pub size: u32,
+}
+
+// 容器 user namespace 的 uid_map 和 gid_map
+// root 用户使用 /etc/subuid、/etc/subgid 中分配给 root 的范围，容器中的 root 不是宿主机的 root
+// 普通用户（rootless）把自己映射为容器中的 root，/etc/subuid 中分配给自己的范围映射为容器中的其余用户
+pub struct UserNamespace {
+    pub uid_map: Vec<IdMap>,
+    pub gid_map: Vec<IdMap>,
+}
+
+impl UserNamespace {
+    pub fn new() -> Result<Self> {
+        let uid = geteuid().as_raw();
+        let gid = getegid().as_raw();
+        // /etc/subuid 中的用户既可以写用户名也可以写 uid
+        let user = User::from_uid(geteuid()).ok().flatten().map(|user| user.name).unwrap_or_else(|| uid.to_string());
+        let subuid = read_subid(SUBUID_FILE, &user, uid)?;
+        let subgid = read_subid(SUBGID_FILE, &user, uid)?;
+
+        if !is_rootless() {
+            let (Some(subuid), Some(subgid)) = (subuid, subgid) else {
+                return Err(Error::InvalidArgument(format!(
+                    "No subordinate id range for {} in {} or {}", user, SUBUID_FILE, SUBGID_FILE
+                )));
+            };
+            return Ok(UserNamespace {
+                uid_map: vec![IdMap { container_id: 0, ..subuid }],
+                gid_map: vec![IdMap { container_id: 0, ..subgid }],
+            });
+        }
+
+        let id_map = |own: u32, sub: Option<IdMap>| {
+            std::iter::once(IdMap { container_id: 0, host_id: own, size: 1 })
+                .chain(sub.map(|sub| IdMap { container_id: 1, ..sub }))
+                .collect()
+        };
+        Ok(UserNamespace { uid_map: id_map(uid, subuid), gid_map: id_map(gid, subgid) })
+    }
+
+    // 容器中的 root 在宿主机上的 uid 和 gid
+    pub fn root_owner(&self) -> (u32, u32) {
+        (self.uid_map[0].host_id, self.gid_map[0].host_id)
+    }
+
+    // 在 clone 之后、init 进程继续执行之前调用
+    // 普通用户只能直接写入一行映射到自己的 ID，映射 subuid 范围需要借助 setuid 的 newuidmap/newgidmap
+    pub fn write_maps(&self, pid: Pid) -> Result<()> {
+        info!("Writing uid_map and gid_map of process {}", pid);
+        if is_rootless() && self.uid_map.len() > 1 {
+            newidmap("newuidmap", pid, &self.uid_map)?;
+        } else {
+            write_map(pid, "uid_map", &self.uid_map)?;
+        }
+
+        if is_rootless() && self.gid_map.len() > 1 {
+            newidmap("newgidmap", pid, &self.gid_map)?;
+        } else {
+            // 没有 CAP_SETGID 的进程写 gid_map 之前必须禁用 setgroups
+            if is_rootless() {
+                let setgroups = format!("/proc/{}/setgroups", pid);
+                std::fs::write(&setgroups, "deny").context(format!("Failed to write {}", setgroups))?;
+            }
+            write_map(pid, "gid_map", &self.gid_map)?;
+        }
+        Ok(())
+    }
+}
+
+// 返回 /etc/subuid 或 /etc/subgid 中分配给用户的第一段范围，每行形如 user:100000:65536
+fn read_subid(file: &str, user: &str, id: u32) -> Result<Option<IdMap>> {
+    let content = match std::fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(format!("Failed to read {}", file)),
+    };
+    let id = id.to_string();
+    let range = content.lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().split(':');
+            let (name, start, size) = (fields.next()?, fields.next()?, fields.next()?);
+            if name != user && name != id {
+                return None;
+            }
+            Some(IdMap { container_id: 0, host_id: start.parse().ok()?, size: size.parse().ok()? })
+        })
+        .find(|range| range.size > 0);
+    Ok(range)
+}
+
+fn write_map(pid: Pid, file: &str, id_map: &[IdMap]) -> Result<()> {
+    let path = format!("/proc/{}/{}", pid, file);
+    let content: String = id_map.iter()
+        .map(|m| format!("{} {} {}\n", m.container_id, m.host_id, m.size))
+        .collect();
+    std::fs::write(&path, content).context(format!("Failed to write {}", path))
+}
+
+fn newidmap(program: &str, pid: Pid, id_map: &[IdMap]) -> Result<()> {
+    // newuidmap <pid> <container_id> <host_id> <size> ...
+    let args = id_map.iter().flat_map(|m| [m.container_id, m.host_id, m.size]).map(|id| id.to_string());
+    let output = Command::new(program)
+        .arg(pid.to_string())
+        .args(args)
+        .output()
+        .context(format!("Failed to execute {}", program))?;
+    if !output.status.success() {
+        return Err(Error::Runtime(format!(
+            "{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+// 在新的 user namespace 中切换为容器中的 root
+// 父进程写入 uid_map 之前，clone 出的进程在 namespace 中是 overflow uid（65534）
+pub fn become_root() -> Result<()> {
+    // rootless 且没有 subgid 时 setgroups 已被禁用
+    match setgroups(&[]) {
+        Ok(()) | Err(Errno::EPERM) => {}
+        Err(e) => return Err(e).context("setgroups failed"),
+    }
+    setresgid(Gid::from_raw(0), Gid::from_raw(0), Gid::from_raw(0)).context("setresgid failed")?;
+    setresuid(Uid::from_raw(0), Uid::from_raw(0), Uid::from_raw(0)).context("setresuid failed")
+}
+
+// root 用户使用 user namespace 时，镜像中的文件要改为映射后的属主，否则在容器中属于 nobody
+// 只在解压镜像之后调用一次，不在映射范围内的 ID 保持不变
+pub fn shift_ownership(path: &Path, userns: &UserNamespace) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(path).context(format!("Failed to stat {}", path.display()))?;
+    let uid = shift_id(metadata.uid(), &userns.uid_map);
+    let gid = shift_id(metadata.gid(), &userns.gid_map);
+    lchown(path, uid, gid).context(format!("Failed to chown {}", path.display()))?;
+    // chown 会清除 setuid 和 setgid 位，需要恢复原来的权限
+    if !metadata.is_symlink() && metadata.mode() & 0o6000 != 0 {
+        std::fs::set_permissions(path, metadata.permissions()).context(format!("Failed to chmod {}", path.display()))?;
+    }
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path).context(format!("Failed to read directory {}", path.display()))? {
+            let entry = entry.context(format!("Failed to read directory {}", path.display()))?;
+            shift_ownership(&entry.path(), userns)?;
+        }
+    }
+    Ok(())
+}
+
+fn shift_id(id: u32, id_map: &[IdMap]) -> Option<u32> {
+    id_map.iter()
+        .find(|m| id >= m.container_id && id - m.container_id < m.size)
+        .map(|m| m.host_id + (id - m.container_id))
+}
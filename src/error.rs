@@ -0,0 +1,115 @@
+use std::fmt;
+
+use nix::errno::Errno;
+
+// mydocker 各个子命令统一返回的错误类型
+// 进程的退出码与 docker 一致：125 表示 mydocker 自身出错，126 表示命令无法执行，127 表示找不到命令
+#[derive(Debug)]
+pub enum Error {
+    Io { context: String, source: std::io::Error },
+    Sys { context: String, source: Errno },
+    Netlink { context: String, source: rtnetlink::Error },
+    Json { context: String, source: serde_json::Error },
+    InvalidArgument(String),
+    NoSuchContainer(String),
+    NoSuchImage(String),
+    NoSuchNetwork(String),
+    Conflict(String),           // 如容器名已被占用、容器正在运行
+    Runtime(String),
+    Exec { command: String, errno: Errno },     // 容器的命令或 exec 的命令无法执行
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub const EXIT_RUNTIME_ERROR: i32 = 125;
+pub const EXIT_NOT_EXECUTABLE: i32 = 126;
+pub const EXIT_COMMAND_NOT_FOUND: i32 = 127;
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Exec { errno: Errno::ENOENT | Errno::ENOTDIR, .. } => EXIT_COMMAND_NOT_FOUND,
+            Error::Exec { .. } => EXIT_NOT_EXECUTABLE,
+            _ => EXIT_RUNTIME_ERROR,
+        }
+    }
+
+    // execvp 失败的 errno 决定退出码是 126 还是 127
+    pub fn exec(command: &str, errno: Errno) -> Self {
+        Error::Exec { command: command.to_string(), errno }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { context, source } => write!(f, "{}: {}", context, source),
+            Error::Sys { context, source } => write!(f, "{}: {}", context, source.desc()),
+            Error::Netlink { context, source } => write!(f, "{}: {}", context, source),
+            Error::Json { context, source } => write!(f, "{}: {}", context, source),
+            Error::InvalidArgument(message) => write!(f, "{}", message),
+            Error::NoSuchContainer(name) => write!(f, "No such container: {}", name),
+            Error::NoSuchImage(image) => write!(f, "No such image: {}", image),
+            Error::NoSuchNetwork(network) => write!(f, "No such network: {}", network),
+            Error::Conflict(message) => write!(f, "{}", message),
+            Error::Runtime(message) => write!(f, "{}", message),
+            Error::Exec { command, errno: Errno::ENOENT | Errno::ENOTDIR } => {
+                write!(f, "exec: \"{}\": executable file not found in $PATH", command)
+            }
+            Error::Exec { command, errno } => write!(f, "exec: \"{}\": {}", command, errno.desc()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Sys { source, .. } => Some(source),
+            Error::Netlink { source, .. } => Some(source),
+            Error::Json { source, .. } => Some(source),
+            Error::Exec { errno, .. } => Some(errno),
+            _ => None,
+        }
+    }
+}
+
+// 底层库的错误加上出错时正在做的事情，转换为 Error
+pub trait IntoError {
+    fn into_error(self, context: String) -> Error;
+}
+
+impl IntoError for std::io::Error {
+    fn into_error(self, context: String) -> Error {
+        Error::Io { context, source: self }
+    }
+}
+
+impl IntoError for Errno {
+    fn into_error(self, context: String) -> Error {
+        Error::Sys { context, source: self }
+    }
+}
+
+impl IntoError for rtnetlink::Error {
+    fn into_error(self, context: String) -> Error {
+        Error::Netlink { context, source: self }
+    }
+}
+
+impl IntoError for serde_json::Error {
+    fn into_error(self, context: String) -> Error {
+        Error::Json { context, source: self }
+    }
+}
+
+// 用法：std::fs::read(path).context(format!("Failed to read {}", path))?
+pub trait Context<T> {
+    fn context(self, context: impl Into<String>) -> Result<T>;
+}
+
+impl<T, E: IntoError> Context<T> for std::result::Result<T, E> {
+    fn context(self, context: impl Into<String>) -> Result<T> {
+        self.map_err(|e| e.into_error(context.into()))
+    }
+}
@@ -1,58 +1,95 @@
 use libc::{syscall, SYS_pidfd_open};
-use nix::sched::{setns, CloneFlags};
-use nix::unistd::execvp;
 use log::error;
-use std::ffi::CString;
+use nix::errno::Errno;
+use nix::sched::{setns, CloneFlags};
+use std::io::Write;
 use std::os::fd::{FromRawFd, OwnedFd, RawFd};
 
 use crate::ExecCommand;
-use crate::container::get_pid;
+use crate::cgroupsv2::CGroupManager;
+use crate::container::{
+    become_root, capability_mask, command_cstrings, exec_command, get_capabilities, container_env, container_workdir, get_command, get_pid, load_image_config,
+    merge_env, prepare_exec, resolve_container_id, resolve_env, SyncPipe,
+};
+use crate::error::{Context, Result};
+use crate::process::{self, spawn};
+use crate::run::use_userns;
+use crate::seccomp::seccomp_filter;
 
-pub fn exec(command: ExecCommand) {
-    let container_id = command.container_id.clone();
-    enter_container_ns(&container_id);
-    let args = std::iter::once(&command.command).chain(command.args.iter())
-        .map(|arg| CString::new(arg.clone()).unwrap())
-        .collect::<Vec<_>>();
-    execvp(&CString::new(command.command.clone()).unwrap(), args.as_slice()).expect("execvp failed");
-}
+// 进入容器的 namespace 后 clone 出子进程执行命令，返回命令的退出码
+// setns 进入 PID namespace 只对之后创建的子进程生效，所以不能直接在当前进程中 execve
+pub fn exec(command: ExecCommand) -> Result<i32> {
+    let container_id = resolve_container_id(&command.container_id)?;
+    let argv = command_cstrings(&command.command, &command.args)?;
+    // 命令继承容器的环境变量、工作目录和用户，-e、-w、-u 可以覆盖
+    let run_command = get_command(&container_id)?;
+    let image = load_image_config(&run_command.image)?;
+    let env = merge_env(
+        container_env(&container_id, &run_command, &image),
+        &resolve_env(&command.env, &command.env_file)?,
+    );
+    let workdir = container_workdir(command.workdir.as_deref().or(run_command.workdir.as_deref()), &image)?;
+    let user = command.user.or(run_command.user.clone());
+    let capabilities = get_capabilities(&container_id)?;
+    let seccomp = seccomp_filter(&run_command, &capabilities)?;
+    let capabilities = capability_mask(&capabilities);
+    let userns = use_userns(&run_command);
+    // 命令与容器中的其他进程受同样的资源限制，在容器的 cgroup namespace 中看到的也是容器自己的 cgroup
+    let cgroup_procs = CGroupManager::new(container_id.clone()).open_procs()?;
+    enter_container_ns(&container_id, userns)?;
 
-pub fn enter_container_ns(container_id: &str) {
-    let pid = get_pid(container_id);
-    let pidfd;
-    unsafe {
-        pidfd = syscall(SYS_pidfd_open, pid, 0);
-        if pidfd < 0 {
-            error!("Error: pidfd_open failed");
-            return;
-        }
-    }
-    let flags = 
-        CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWIPC | CloneFlags::CLONE_NEWNET | 
-        CloneFlags::CLONE_NEWUTS | CloneFlags::CLONE_NEWPID;
-    let pidfd = unsafe {
-        OwnedFd::from_raw_fd(pidfd as RawFd)
+    // 与 run 一样通过同步通道取回 execve 的错误
+    let sync = SyncPipe::new()?;
+    let child_sync = sync.child_end();
+    let pid = spawn(CloneFlags::empty(), || {
+        // execve 成功时不会返回
+        let prepared = child_sync.wait_resume()
+            .and_then(|_| if userns { become_root() } else { Ok(()) })
+            .and_then(|_| prepare_exec(user.as_deref(), &workdir, &env, capabilities, seccomp.as_ref()));
+        let e = match prepared {
+            Ok(env) => exec_command(&command.command, &argv, &env),
+            Err(e) => e,
+        };
+        error!("Error: {}", e);
+        let code = e.exit_code();
+        child_sync.report(e);
+        code
+    })?;
+
+    let mut sync = sync.into_parent();
+    let joined = match cgroup_procs {
+        Some(mut procs) => procs.write_all(pid.to_string().as_bytes())
+            .context(format!("Failed to add process {} to the container's cgroup", pid)),
+        None => Ok(()),
     };
-    setns(pidfd, flags).unwrap_or_else(|e| {
-        error!("Error: setns failed: {}", e);
-    });
+    let started = joined.and_then(|_| sync.resume()).and_then(|_| sync.wait_exec());
+    let (code, _) = process::wait(pid);
+    started.map(|_| code)
 }
 
-pub fn enter_container_netns(container_id: &str) {
-    let pid = get_pid(container_id);
-    let pidfd;
-    unsafe {
-        pidfd = syscall(SYS_pidfd_open, pid, 0);
-        if pidfd < 0 {
-            error!("Error: pidfd_open failed");
-            return;
-        }
+// 容器运行在 user namespace 中时要一同进入，才有权限进入其余的 namespace
+pub fn enter_container_ns(container_id: &str, userns: bool) -> Result<()> {
+    let mut flags =
+        CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWIPC | CloneFlags::CLONE_NEWNET |
+        CloneFlags::CLONE_NEWUTS | CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWCGROUP;
+    if userns {
+        flags |= CloneFlags::CLONE_NEWUSER;
     }
+    setns(container_pidfd(container_id)?, flags)
+        .context(format!("Failed to enter namespaces of container {}", container_id))
+}
+
+pub fn enter_container_netns(container_id: &str) -> Result<()> {
     let flags = CloneFlags::CLONE_NEWNET;
-    let pidfd = unsafe {
-        OwnedFd::from_raw_fd(pidfd as RawFd)
-    };
-    setns(pidfd, flags).unwrap_or_else(|e| {
-        error!("Error: setns failed: {}", e);
-    });
+    setns(container_pidfd(container_id)?, flags)
+        .context(format!("Failed to enter network namespace of container {}", container_id))
+}
+
+fn container_pidfd(container_id: &str) -> Result<OwnedFd> {
+    let pid = get_pid(container_id)?;
+    let pidfd = unsafe { syscall(SYS_pidfd_open, pid, 0) };
+    if pidfd < 0 {
+        return Err(Errno::last()).context(format!("pidfd_open {} failed", pid));
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(pidfd as RawFd) })
 }
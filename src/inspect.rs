@@ -0,0 +1,188 @@
+use serde_json::{json, Map, Value};
+
+use crate::InspectCommand;
+use crate::cgroupsv2::CGroupManager;
+use crate::container::{get_command, get_endpoint, metainfo_json, overlay_layers, resolve_container_id};
+use crate::error::{Context, Error, Result};
+
+pub fn inspect(command: InspectCommand) -> Result<()> {
+    let container_id = resolve_container_id(&command.container_id)?;
+
+    let document = inspect_document(&container_id)?;
+    match command.format {
+        Some(template) => println!("{}", render_template(&template, &document)),
+        None => println!("{}", serde_json::to_string_pretty(&document).context("Failed to serialize inspect document")?),
+    }
+    Ok(())
+}
+
+// 合并元信息、cgroup 的限制与使用量、overlayfs 各层、volume 和网络信息
+fn inspect_document(container_id: &str) -> Result<Value> {
+    let run_command = get_command(container_id)?;
+    let mut document = metainfo_json(container_id)?;
+    let object = document.as_object_mut()
+        .ok_or_else(|| Error::Runtime(format!("Metainfo of container {} is not a JSON object", container_id)))?;
+    object.remove("endpoint");
+
+    let cgroupv2_manager = CGroupManager::new(container_id.to_string());
+    let mut cgroup = Map::new();
+    cgroup.insert("path".to_string(), json!(cgroupv2_manager.get_path()));
+    for (file, content) in cgroupv2_manager.stats() {
+        insert_cgroup_stat(&mut cgroup, &file, &content);
+    }
+    object.insert("cgroup".to_string(), Value::Object(cgroup));
+
+    let layers = overlay_layers(container_id, &run_command.image);
+
+    let mounts = match run_command.volume.as_deref().and_then(|volume| volume.split_once(':')) {
+        Some((source, destination)) => json!([{
+            "source": source,
+            "destination": destination,
+            "mount_point": format!("{}{}", layers.merged, destination),
+        }]),
+        None => json!([]),
+    };
+    object.insert("overlay".to_string(), json!(layers));
+    object.insert("mounts".to_string(), mounts);
+
+    let network = match get_endpoint(container_id)? {
+        Some(ep) => json!({
+            "name": ep.network_name,
+            "ip": ep.ip.to_string(),
+            "veth": ep.veth_name,
+            "peer": ep.peer_name,
+        }),
+        None => Value::Null,
+    };
+    object.insert("network".to_string(), network);
+
+    Ok(document)
+}
+
+// memory.current 放到 cgroup.memory.current 下，方便用 --format 取值
+// --format 按 . 分隔路径，cpuset.cpus.effective 这样有多个 . 的文件名把其余的 . 换成 _，放到 cgroup.cpuset.cpus_effective 下
+fn insert_cgroup_stat(cgroup: &mut Map<String, Value>, file: &str, content: &str) {
+    let (controller, key) = file.split_once('.').unwrap_or((file, ""));
+    let entry = cgroup.entry(controller.to_string()).or_insert_with(|| json!({}));
+    entry[key.replace('.', "_")] = cgroup_value(content);
+}
+
+// 将 cgroup 接口文件的内容转换成 JSON：
+// 形如 "anon 123\nfile 456" 的多行内容转换为对象，单个数字转换为数字，其余（如 "max 100000"）保留字符串
+fn cgroup_value(content: &str) -> Value {
+    if content.contains('\n') {
+        let fields = content.lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(key, value)| (key.to_string(), cgroup_value(value.trim())))
+            .collect::<Map<_, _>>();
+        return Value::Object(fields);
+    }
+    match content.parse::<u64>() {
+        Ok(number) => json!(number),
+        Err(_) => json!(content),
+    }
+}
+
+// 支持 docker inspect --format 中最常用的两种写法：{{.a.b}} 和 {{json .a.b}}
+fn render_template(template: &str, document: &Value) -> String {
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        // 没有闭合的 {{ 及之后的内容原样输出
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        output.push_str(&evaluate(after[..end].trim(), document));
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+    output
+}
+
+fn evaluate(expression: &str, document: &Value) -> String {
+    let (as_json, path) = match expression.strip_prefix("json ") {
+        Some(path) => (true, path.trim()),
+        None => (false, expression),
+    };
+    match lookup(document, path) {
+        Some(Value::String(s)) if !as_json => s.clone(),
+        Some(value) => value.to_string(),
+        None => "<no value>".to_string(),
+    }
+}
+
+fn lookup<'a>(document: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.strip_prefix('.')?;
+    if path.is_empty() {
+        return Some(document);
+    }
+    path.split('.').try_fold(document, |value, key| match value {
+        Value::Array(items) => key.parse::<usize>().ok().and_then(|index| items.get(index)),
+        _ => value.get(key),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> Value {
+        json!({
+            "id": "abc",
+            "pid": 42,
+            "command": { "args": ["-c", "sleep 1"] },
+            "cgroup": { "memory": { "current": 4096 } },
+        })
+    }
+
+    #[test]
+    fn render_fields() {
+        let document = document();
+        assert_eq!(render_template("{{.id}} {{ .pid }}", &document), "abc 42");
+        assert_eq!(render_template("{{.command.args.1}}", &document), "sleep 1");
+        assert_eq!(render_template("{{json .id}}", &document), "\"abc\"");
+        assert_eq!(render_template("{{json .cgroup.memory}}", &document), r#"{"current":4096}"#);
+        assert_eq!(render_template("{{.missing}}", &document), "<no value>");
+        assert_eq!(render_template("no placeholders", &document), "no placeholders");
+    }
+
+    #[test]
+    fn render_unclosed_placeholder() {
+        let document = document();
+        assert_eq!(render_template("a {{.pid", &document), "a {{.pid");
+        assert_eq!(render_template("x {{.id}} y {{.pid", &document), "x abc y {{.pid");
+        assert_eq!(render_template("{{.id}}}}", &document), "abc}}");
+    }
+
+    #[test]
+    fn lookup_paths() {
+        let document = document();
+        assert_eq!(lookup(&document, "."), Some(&document));
+        assert_eq!(lookup(&document, ".command.args.0"), Some(&json!("-c")));
+        assert_eq!(lookup(&document, ".command.args.5"), None);
+        assert_eq!(lookup(&document, "id"), None);
+    }
+
+    #[test]
+    fn cgroup_values() {
+        assert_eq!(cgroup_value("123"), json!(123));
+        assert_eq!(cgroup_value("max 100000"), json!("max 100000"));
+        assert_eq!(cgroup_value("0-3"), json!("0-3"));
+        assert_eq!(cgroup_value("low 0\nhigh 0\noom_kill 1"), json!({ "low": 0, "high": 0, "oom_kill": 1 }));
+    }
+
+    #[test]
+    fn dotted_stat_files_are_reachable() {
+        let mut cgroup = Map::new();
+        insert_cgroup_stat(&mut cgroup, "memory.current", "4096");
+        insert_cgroup_stat(&mut cgroup, "cpuset.cpus.effective", "0-3");
+        insert_cgroup_stat(&mut cgroup, "hugetlb.2MB.current", "0");
+        let document = json!({ "cgroup": cgroup });
+        assert_eq!(render_template("{{.cgroup.memory.current}}", &document), "4096");
+        assert_eq!(render_template("{{.cgroup.cpuset.cpus_effective}}", &document), "0-3");
+        assert_eq!(render_template("{{.cgroup.hugetlb.2MB_current}}", &document), "0");
+    }
+}
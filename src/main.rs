@@ -9,6 +9,14 @@ mod stop;
 mod mydocker_log;
 mod exec;
 mod prune;
+mod attach;
+mod shim;
+mod inspect;
+mod error;
+mod rollback;
+mod process;
+mod state;
+mod seccomp;
 
 use simple_logger::SimpleLogger;
 use clap::{Parser, Subcommand};
@@ -22,18 +30,25 @@ use rm::rm;
 use mydocker_log::log;
 use exec::exec;
 use prune::prune;
+use attach::attach;
+use inspect::inspect;
 use network::*;
+use error::Result;
 
 #[derive(Parser)]
 #[command(author)]
 struct Cli {
+    #[arg(long, global = true, env = "MYDOCKER_ROOT")]
+    root: Option<String>,           // 状态保存的根目录，同一台机器上的多个 mydocker 实例互相隔离
+    #[arg(long, global = true, env = "MYDOCKER_CGROUP_PARENT")]
+    cgroup_parent: Option<String>,  // 容器 cgroup 的父目录，相对于 /sys/fs/cgroup
     #[command(subcommand)]
     subcommand: DockerSubCmd,
 }
 
 #[derive(Subcommand)]
 enum DockerSubCmd {
-    Run(RunCommand),
+    Run(Box<RunCommand>),   // RunCommand 的选项较多，装箱避免其余子命令也占用同样大的空间
     Commit(CommitCommand),
     Ps(PsCommand),
     Stop(StopCommand),
@@ -41,6 +56,8 @@ enum DockerSubCmd {
     Rm(RmCommand),
     Log(LogCommand),
     Exec(ExecCommand),
+    Attach(AttachCommand),
+    Inspect(InspectCommand),
     Prune(PruneCommand),
     Network(NetworkCommand),
 }
@@ -51,12 +68,85 @@ struct RunCommand {
     cpu: Option<u32>,
     #[arg(long)]
     mem: Option<String>,
+    #[arg(long)]
+    cpuset_cpus: Option<String>,    // 允许使用的 CPU，如 0-3,5
+    #[arg(long)]
+    cpuset_mems: Option<String>,    // 允许使用的内存节点
+    #[arg(long)]
+    #[serde(default)]
+    device_read_bps: Vec<String>,   // 形如 /dev/sda:10mb，限制块设备每秒读取的字节数
+    #[arg(long)]
+    #[serde(default)]
+    device_write_bps: Vec<String>,
+    #[arg(long)]
+    blkio_weight: Option<u16>,      // 块设备 IO 的相对权重，10 到 1000
+    #[arg(long)]
+    pids_limit: Option<i64>,        // 容器中的最大进程数，0 和负数表示不限制
+    #[arg(long)]
+    #[serde(default)]
+    hugetlb_limit: Vec<String>,     // 形如 2MB:1g，限制每种大页的用量
     #[arg(long, short)]
     volume: Option<String>,
     #[arg(long, short)]
     detach: bool,
     #[arg(long, short)]
+    #[serde(default)]
+    interactive: bool,  // 保持容器的标准输入打开
+    #[arg(long, short)]
+    #[serde(default)]
+    tty: bool,          // 为容器分配一个伪终端
+    #[arg(long, short)]
     net: Option<String>,
+    #[arg(long)]
+    name: Option<String>,   // 容器名，可以代替容器 ID 使用
+    #[arg(long)]
+    #[serde(default)]
+    userns: bool,           // 在新的 user namespace 中运行容器，rootless 模式下总是开启
+    #[arg(long)]
+    hostname: Option<String>,   // 默认为容器的短 ID
+    #[arg(long)]
+    #[serde(default)]
+    dns: Vec<String>,           // 默认沿用宿主机 /etc/resolv.conf 中的 nameserver
+    #[arg(long)]
+    #[serde(default)]
+    dns_search: Vec<String>,
+    #[arg(long)]
+    #[serde(default)]
+    add_host: Vec<String>,      // 形如 name:ip，追加到容器的 /etc/hosts
+    #[arg(long, short)]
+    #[serde(default)]
+    env: Vec<String>,           // 形如 KEY=VALUE，run 时已经合并了 --env-file 中的变量
+    #[arg(long)]
+    #[serde(default)]
+    env_file: Vec<String>,
+    #[arg(long, short)]
+    workdir: Option<String>,    // 默认使用镜像中的工作目录或者 /
+    #[arg(long, short)]
+    user: Option<String>,       // user[:group]，名字或数字，默认为 root
+    #[arg(long)]
+    #[serde(default)]
+    cap_add: Vec<String>,       // 在 docker 默认的 capability 之外增加，ALL 表示全部
+    #[arg(long)]
+    #[serde(default)]
+    cap_drop: Vec<String>,
+    #[arg(long)]
+    #[serde(default)]
+    privileged: bool,           // 保留全部 capability
+    #[arg(long)]
+    #[serde(default)]
+    security_opt: Vec<String>,  // 目前只支持 seccomp=<file|unconfined>，默认使用 docker 的 seccomp 配置
+    #[arg(long)]
+    #[serde(default)]
+    read_only: bool,            // 根文件系统只读，挂载的 volume 和 tmpfs 不受影响
+    #[arg(long)]
+    #[serde(default)]
+    tmpfs: Vec<String>,         // 形如 /path[:opts]，opts 与 mount -o 相同
+    #[arg(long)]
+    #[serde(default)]
+    device: Vec<String>,        // 形如 /dev/host[:/dev/container[:rwm]]，把宿主机的设备加入容器
+    #[arg(long)]
+    #[serde(default)]
+    device_cgroup_rule: Vec<String>,    // 形如 'c 1:3 rwm'，允许访问的设备，major、minor 可以为 *
     image: String,
     command: String,
     args: Vec<String>,
@@ -71,7 +161,13 @@ struct CommitCommand {
 #[derive(Parser)]
 struct PsCommand {
     #[arg(long, short)]
-    all: bool,
+    all: bool,                  // 同时显示已经退出的容器
+    #[arg(long, short)]
+    quiet: bool,                // 只输出容器 ID
+    #[arg(long, short)]
+    filter: Vec<String>,        // 如 status=exited、name=web、ancestor=busybox
+    #[arg(long)]
+    format: Option<String>,     // 目前只支持 json
 }
 
 #[derive(Parser)]
@@ -96,11 +192,33 @@ struct LogCommand {
 
 #[derive(Parser)]
 struct ExecCommand {
+    #[arg(long, short)]
+    env: Vec<String>,           // 追加到容器的环境变量之后
+    #[arg(long)]
+    env_file: Vec<String>,
+    #[arg(long, short)]
+    workdir: Option<String>,    // 默认与容器的工作目录相同
+    #[arg(long, short)]
+    user: Option<String>,       // 默认与容器的用户相同
     container_id: String,
     command: String,
     args: Vec<String>,
 }
 
+#[derive(Parser)]
+struct AttachCommand {
+    container_id: String,
+}
+
+#[derive(Parser)]
+struct InspectCommand {
+    // 如 {{.pid}}、{{json .cgroup.memory}}；cgroup 接口文件名中控制器之后的 . 换成了 _，如 {{.cgroup.cpuset.cpus_effective}}
+    #[arg(long, short, help = "Format the output using a template like {{.pid}} or {{json .cgroup.memory}}; \
+        dots after the controller in cgroup file names become underscores, e.g. {{.cgroup.cpuset.cpus_effective}}")]
+    format: Option<String>,
+    container_id: String,
+}
+
 #[derive(Parser)]
 struct PruneCommand {
     
@@ -137,40 +255,58 @@ fn main() {
     
     let cli = Cli::parse();
     register_driver("bridge", Box::new(Bridge {}));
-    match cli.subcommand {
+    match state::init(cli.root, cli.cgroup_parent).and_then(|_| execute(cli.subcommand)) {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("mydocker: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+// 返回 mydocker 的退出码，run 和 start 在前台运行时为容器的退出码，exec 为命令的退出码
+fn execute(subcommand: DockerSubCmd) -> Result<i32> {
+    match subcommand {
         DockerSubCmd::Run(run_command) => {
-            run(run_command);
+            return run(*run_command);
         },
         DockerSubCmd::Commit(commit_command) => {
-            commit_container(&commit_command.container_id, &commit_command.image);
+            commit_container(&commit_command.container_id, &commit_command.image)?;
         },
         DockerSubCmd::Ps(ps_command) => {
-            ps(ps_command);
+            ps(ps_command)?;
         },
         DockerSubCmd::Stop(stop_command) => {
-            stop(stop_command);
+            stop(stop_command)?;
         },
         DockerSubCmd::Start(start_command) => {
-            start(start_command);
+            return start(start_command);
         },
         DockerSubCmd::Rm(rm_command) => {
-            rm(rm_command);
+            rm(rm_command)?;
         },
         DockerSubCmd::Log(log_command) => {
-            log(&log_command.container_id);
+            log(&log_command.container_id)?;
         },
         DockerSubCmd::Exec(exec_command) => {
-            exec(exec_command);
+            return exec(exec_command);
+        },
+        DockerSubCmd::Attach(attach_command) => {
+            attach(attach_command)?;
+        },
+        DockerSubCmd::Inspect(inspect_command) => {
+            inspect(inspect_command)?;
         },
         DockerSubCmd::Prune(_) => {
-            prune();
+            prune()?;
         }
         DockerSubCmd::Network(network_command) => {
             match network_command.subcommand {
                 NetworkSubCommand::Create(create_network_command) => {
-                    create_network(create_network_command);
+                    create_network(create_network_command)?;
                 },
             }
         }
     }
+    Ok(0)
 }
@@ -1,7 +1,11 @@
-use crate::container::METAINFO_BASE_PATH;
+use crate::container::resolve_container_id;
+use crate::state::metainfo_base_path;
+use crate::error::{Context, Result};
 
-pub fn log(container_id: &str) {
-    let log_path = format!("{}{}/container.log", METAINFO_BASE_PATH, container_id);
-    let buffer = std::fs::read_to_string(log_path).expect("Failed to read log file");
+pub fn log(name_or_id: &str) -> Result<()> {
+    let container_id = resolve_container_id(name_or_id)?;
+    let log_path = format!("{}{}/container.log", metainfo_base_path(), container_id);
+    let buffer = std::fs::read_to_string(&log_path).context(format!("Failed to read log file {}", log_path))?;
     println!("{}", buffer);
+    Ok(())
 }
\ No newline at end of file
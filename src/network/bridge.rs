@@ -1,25 +1,27 @@
-use std::fmt::format;
 use futures::stream::TryStreamExt;
 
 // Bridge 是一种 NetworkDriver，用于创建基于 Bridge 的 Network
 use crate::network::*;
+use crate::error::{Context, Error, Result};
 use rtnetlink::{LinkBridge, LinkVeth, LinkUnspec};
 use log::error;
+use crate::state::state_root;
 
 pub struct Bridge {
 
 }
 
 impl NetworkDriver for Bridge {
-    fn create(&self, subnet: SubNet, name: &str) -> Network {
+    fn create(&self, subnet: SubNet, name: &str) -> Result<Network> {
         // 创建网络
         let network = Network::new(name, "bridge");
-        // 记录网络的网段信息
-        register_network_subnet(name, subnet);
 
         // 为网络创建网桥（交换机 + 路由器）
-        self.init_bridge(&network);
-        network
+        self.init_bridge(&network)?;
+
+        // 记录网络的网段信息
+        register_network_subnet(name, subnet)?;
+        Ok(network)
     }
 
     fn delete(&self) {
@@ -27,28 +29,35 @@ impl NetworkDriver for Bridge {
     }
 
     // 创建一对 Veth 设备，将其中一个添加到桥设备上
-    fn connect(&self, network_name: &str, ep: &mut Endpoint) {
+    fn connect(&self, network_name: &str, ep: &mut Endpoint) -> Result<()> {
         // 连接网络
         log::info!("here.");
-        let veth_name = format!("veth{}", &ep.id[0..5]);
-        let veth_peer_name = format!("veth{}peer", &ep.id[0..5]);
-        ep.peer_name = Some(veth_peer_name.clone());
-        TOKIO.block_on(async {
-            // ip link add veth0 type veth peer name veth1
-            HANDLE
+        let (veth_name, veth_peer_name) = veth_names(&ep.id);
+        let (runtime, handle) = netlink()?;
+        // ip link add veth0 type veth peer name veth1
+        // 同名的 veth 已经存在时创建失败，此时不能记下名字，否则撤销时会删掉别人的 veth
+        runtime.block_on(async {
+            handle
                 .link()
                 .add(LinkVeth::new(&veth_name, &veth_peer_name).build())
                 .execute()
                 .await
-                .unwrap();
-            log::info!("Created veth pair: {} and {}", veth_name, veth_peer_name);
+                .context(format!("Failed to create veth pair {} and {}", veth_name, veth_peer_name))
+        })?;
+        log::info!("Created veth pair: {} and {}", veth_name, veth_peer_name);
+        ep.veth_name = Some(veth_name.clone());
+        ep.peer_name = Some(veth_peer_name.clone());
 
-            let mut bridges = HANDLE.link().get().match_name(network_name.to_string()).execute();
+        runtime.block_on(async {
+            let mut bridges = handle.link().get().match_name(network_name.to_string()).execute();
 
-            let bridge = bridges.try_next().await.unwrap().unwrap().header.index;
+            let bridge = bridges.try_next().await
+                .context(format!("Failed to find bridge {}", network_name))?
+                .ok_or_else(|| Error::NoSuchNetwork(network_name.to_string()))?
+                .header.index;
 
             // ip link set veth0 master bridge
-            HANDLE
+            handle
                 .link()
                 .set(
                     LinkUnspec::new_with_name(&veth_name)
@@ -57,49 +66,87 @@ impl NetworkDriver for Bridge {
                 )
                 .execute()
                 .await
-                .unwrap();
+                .context(format!("Failed to attach {} to bridge {}", veth_name, network_name))?;
 
             // ip link set veth0 up
-            HANDLE
+            handle
                 .link()
                 .set(LinkUnspec::new_with_name(&veth_name).up().build())
                 .execute()
                 .await
-                .unwrap_or_else(|e| { error!("Failed to set veth up: {}", e) });
+                .context(format!("Failed to set {} up", veth_name))
         })
     }
 
-    fn disconnect(&self, endpoint_id: &str) {
-        // 断开网络
+    // 删除宿主机一侧的 veth，另一端随之一起被删除
+    // 容器的 network namespace 销毁时 veth 通常已经不在了，找不到时直接忽略
+    fn disconnect(&self, ep: &Endpoint) -> Result<()> {
+        let veth_name = match &ep.veth_name {
+            Some(veth_name) => veth_name.clone(),
+            None => return Ok(()),
+        };
+        let (runtime, handle) = netlink()?;
+        runtime.block_on(async {
+            let mut links = handle.link().get().match_name(veth_name.clone()).execute();
+            if let Ok(Some(link)) = links.try_next().await {
+                handle
+                    .link()
+                    .del(link.header.index)
+                    .execute()
+                    .await
+                    .context(format!("Failed to delete veth {}", veth_name))?;
+            }
+            Ok(())
+        })
     }
 }
 
+// 网络接口名在宿主机上是全局的，使用不同 --root 的实例中可能有前缀相同的容器 ID
+// 所以用状态根目录和完整容器 ID 的 FNV-1a 哈希命名，加上 peer 后缀也不超过 15 个字符
+fn veth_names(container_id: &str) -> (String, String) {
+    let hash = format!("{}\0{}", state_root(), container_id).bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3));
+    let veth_name = format!("veth{:07x}", hash & 0xfff_ffff);
+    let peer_name = format!("{}peer", veth_name);
+    (veth_name, peer_name)
+}
+
 impl Bridge {
-    fn init_bridge(&self, network: &Network) {
-        TOKIO.block_on(async {
+    fn init_bridge(&self, network: &Network) -> Result<()> {
+        let (runtime, handle) = netlink()?;
+        runtime.block_on(async {
             // ip link add name <bridge_name> type bridge
-            HANDLE
+            handle
                 .link()
                 .add(LinkBridge::new(network.get_name()).build())
                 .execute()
                 .await
-                .unwrap_or_else(|e| { error!("Failed to create bridge: {}", e) });
+                .context(format!("Failed to create bridge {}", network.get_name()))?;
 
             // ip link set <bridge_name> up
-            HANDLE
+            handle
                 .link()
                 .set(LinkUnspec::new_with_name(network.get_name()).up().build())
                 .execute()
                 .await
                 .unwrap_or_else(|e| { error!("Failed to set bridge up: {}", e) });
-        });
-    }
-
-    fn set_bridge_ip(&self, network: &Network) {
-        
+            Ok(())
+        })
     }
+}
 
-    fn set_bridge_up(&self, network: &Network) {
-        
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn veth_names_fit_ifnamsiz_and_use_the_full_id() {
+        let (veth_name, peer_name) = veth_names("0123456789abcdef");
+        assert!(veth_name.starts_with("veth") && veth_name.len() == 11);
+        assert_eq!(peer_name, format!("{}peer", veth_name));
+        assert!(peer_name.len() <= 15);
+        assert_eq!(veth_names("0123456789abcdef").0, veth_name);
+        // 前缀相同的容器 ID 得到不同的名字
+        assert_ne!(veth_names("0123456789abcdee").0, veth_name);
     }
 }
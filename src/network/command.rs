@@ -1,19 +1,26 @@
 use crate::network::*;
 use crate::CreateNetworkCommand;
+use crate::error::{Error, Result};
 
-pub fn create_network(cmd: CreateNetworkCommand) {
+pub fn create_network(cmd: CreateNetworkCommand) -> Result<()> {
     // 这里只做解析参数 + 调用驱动的工作
     let drivers = crate::network::DRIVERS.lock().unwrap();
-    let driver = drivers.get(&cmd.driver).unwrap();
-    let subnet = cmd.subnet.parse().unwrap();
-    let network = driver.create(subnet, &cmd.name);
-    
+    let driver = drivers.get(&cmd.driver)
+        .ok_or_else(|| Error::InvalidArgument(format!("Unknown network driver {}", cmd.driver)))?;
+    let subnet = cmd.subnet.parse()?;
+    if network_exists(&cmd.name)? {
+        return Err(Error::Conflict(format!("Network {} already exists", cmd.name)));
+    }
+    let network = driver.create(subnet, &cmd.name)?;
+
     // 将网络信息持久化到文件中
-    register_network(network);
+    register_network(network)?;
 
     log::info!("Created network: {}", cmd.name);
+    Ok(())
 }
 
+#[allow(dead_code)]     // network ls 子命令尚未实现
 pub fn list_network() {
     
 }
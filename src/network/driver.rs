@@ -1,6 +1,6 @@
 use std::collections::BTreeMap;
 use std::sync::Mutex;
-use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
 use rtnetlink::{new_connection, Handle};
 use tokio::runtime::Runtime;
 
@@ -8,30 +8,34 @@ use tokio::runtime::Runtime;
 // 本项目先实现 Bridge 类型的 NetworkDriver
 // 原生 docker 具有的 NetworkDriver 类型还有 overlay 和 macvlan
 use crate::network::*;
+use crate::error::{Context, Result};
 
 // 用一个全局变量记录可选的 driver，当用户创建网络时，选用用户指定的 driver
 pub static DRIVERS: Mutex<BTreeMap<String, Box<dyn NetworkDriver>>> = Mutex::new(BTreeMap::new());
 
-// 全局 runtime
-pub static TOKIO: Lazy<Runtime> = Lazy::new(|| {
-    tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .unwrap()
-});
+// 全局 runtime 和 netlink handle，第一次使用时创建
+static NETLINK: OnceCell<(Runtime, Handle)> = OnceCell::new();
 
-// 全局 handle
-pub static HANDLE: Lazy<Handle> = Lazy::new(|| {
-    let (connection, handle, _) = new_connection().unwrap();
-    TOKIO.spawn(connection); // 注意这里用全局 runtime
-    handle
-});
+pub fn netlink() -> Result<&'static (Runtime, Handle)> {
+    NETLINK.get_or_try_init(|| {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("Failed to create tokio runtime")?;
+        let (connection, handle, _) = runtime.block_on(async {
+            new_connection()
+        }).context("Failed to connect to netlink")?;
+        runtime.spawn(connection); // 注意这里用全局 runtime
+        Ok((runtime, handle))
+    })
+}
 
 pub trait NetworkDriver: Send + Sync {
-    fn create(&self, subnet: SubNet, name: &str) -> Network;
+    fn create(&self, subnet: SubNet, name: &str) -> Result<Network>;
+    #[allow(dead_code)]     // network rm 子命令尚未实现
     fn delete(&self);
-    fn connect(&self, network_name: &str, ep: &mut Endpoint);
-    fn disconnect(&self, endpoint_id: &str);   
+    fn connect(&self, network_name: &str, ep: &mut Endpoint) -> Result<()>;
+    fn disconnect(&self, ep: &Endpoint) -> Result<()>;
 }
 
 pub fn register_driver(name: &str, driver: Box<dyn NetworkDriver>) {
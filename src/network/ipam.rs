@@ -7,6 +7,9 @@ use serde::{Deserialize, Serialize};
 
 use log::info;
 
+use crate::error::{Context, Error, Result};
+use crate::state::ipam_file;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SubNet {
     ip: [u8; 4], // 子网地址
@@ -15,39 +18,34 @@ pub struct SubNet {
 }
 
 impl FromStr for SubNet {
-    type Err = std::io::Error;
+    type Err = Error;
 
     // from strings like 192.168.0.0/24
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidArgument(format!("Invalid subnet {}: {}", s, reason));
         let parts: Vec<&str> = s.split('/').collect();
         if parts.len() != 2 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid subnet format",
-            ));
+            return Err(invalid("expected a CIDR like 192.168.0.0/24"));
         }
 
         let ip_parts: Vec<&str> = parts[0].split('.').collect();
         if ip_parts.len() != 4 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid IP address format",
-            ));
+            return Err(invalid("invalid IP address format"));
         }
 
         let mut ip = [0; 4];
-        for i in 0..4 {
-            ip[i] = ip_parts[i].parse::<u8>().map_err(|_| {
-                std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid IP address")
-            })?;
+        for (byte, part) in ip.iter_mut().zip(&ip_parts) {
+            *byte = part.parse::<u8>().map_err(|_| invalid("invalid IP address"))?;
         }
 
         let mask = parts[1]
             .parse::<u8>()
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid mask"))?;
+            .ok()
+            .filter(|mask| (1..=30).contains(mask))
+            .ok_or_else(|| invalid("mask must be between 1 and 30"))?;
 
-        let size = 1 << (32 - mask);
-        let mut bitmap = vec![0; (size + 7) / 8];
+        let size: usize = 1 << (32 - mask);
+        let mut bitmap = vec![0; size.div_ceil(8)];
         bitmap[0] = 0b00000001; // 标记第一个 IP 地址为已分配（通常是网络地址）
 
         Ok(SubNet { ip, mask, bitmap })
@@ -56,64 +54,67 @@ impl FromStr for SubNet {
 
 // 保存网络名和网段信息的映射
 static IPAM: Mutex<BTreeMap<String, SubNet>> = Mutex::new(BTreeMap::new());
-const IPAM_FILE: &str = "/root/.mydocker/network/ipam/subnet.json";
 
-fn load_ipam() -> std::io::Result<()> {
-    let file = match std::fs::File::open(IPAM_FILE) {
+fn load_ipam() -> Result<()> {
+    let ipam_file = ipam_file();
+    let file = match std::fs::File::open(&ipam_file) {
         Ok(f) => f,
         Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
             // 文件不存在则创建一个空文件并写入空的 JSON 对象
-            std::fs::create_dir_all(std::path::Path::new(IPAM_FILE).parent().unwrap())?;
-            let mut f = std::fs::File::create(IPAM_FILE)?;
-            f.write_all(b"{}")?;
-            std::fs::File::open(IPAM_FILE)?
+            let dir = std::path::Path::new(&ipam_file).parent()
+                .ok_or_else(|| Error::Runtime(format!("Invalid ipam file path {}", ipam_file)))?;
+            std::fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+            let mut f = std::fs::File::create(&ipam_file).context(format!("Failed to create {}", ipam_file))?;
+            f.write_all(b"{}").context(format!("Failed to write {}", ipam_file))?;
+            std::fs::File::open(&ipam_file).context(format!("Failed to open {}", ipam_file))?
         }
-        Err(e) => return Err(e),
+        Err(e) => return Err(e).context(format!("Failed to open {}", ipam_file)),
     };
     let reader = std::io::BufReader::new(file);
-    let ipam: BTreeMap<String, SubNet> = serde_json::from_reader(reader)?;
+    let ipam: BTreeMap<String, SubNet> = serde_json::from_reader(reader).context(format!("Failed to parse {}", ipam_file))?;
     *IPAM.lock().unwrap() = ipam;
     Ok(())
 }
 
-fn dump_ipam() -> std::io::Result<()> {
+fn dump_ipam() -> Result<()> {
+    let ipam_file = ipam_file();
     let ipam = IPAM.lock().unwrap();
-    let file = std::fs::File::create(IPAM_FILE)?;
+    let file = std::fs::File::create(&ipam_file).context(format!("Failed to create {}", ipam_file))?;
     let writer = std::io::BufWriter::new(file);
-    serde_json::to_writer(writer, &*ipam)?;
-    Ok(())
+    serde_json::to_writer(writer, &*ipam).context(format!("Failed to write {}", ipam_file))
 }
 
-pub fn allocate_ip(network: &str) -> Option<Ipv4Network> {
-    load_ipam().unwrap();
+pub fn allocate_ip(network: &str) -> Result<Ipv4Network> {
+    load_ipam()?;
     let mut ipam = IPAM.lock().unwrap();
-    if let Some(subnet) = ipam.get_mut(network) {
-        if let Some(ip) = subnet.allocate_ip() {
-            let ipv4 = Ipv4Network::new(Ipv4Addr::from(ip), subnet.mask).unwrap();
-            info!("Allocated IP: {} for network: {}", ipv4, network);
-            drop(ipam);
-            dump_ipam().unwrap();
-            return Some(ipv4);
-        }
-    }
-    None
+    let subnet = ipam.get_mut(network).ok_or_else(|| Error::NoSuchNetwork(network.to_string()))?;
+    let ip = subnet.allocate_ip()
+        .ok_or_else(|| Error::Runtime(format!("No available IP address in network {}", network)))?;
+    let ipv4 = Ipv4Network::new(Ipv4Addr::from(ip), subnet.mask)
+        .map_err(|e| Error::Runtime(format!("Invalid IP address {:?}: {}", ip, e)))?;
+    info!("Allocated IP: {} for network: {}", ipv4, network);
+    drop(ipam);
+    dump_ipam()?;
+    Ok(ipv4)
 }
 
-pub fn release_ip(network: &str, ip: [u8; 4]) {
-    load_ipam().unwrap();
+pub fn release_ip(network: &str, ip: [u8; 4]) -> Result<()> {
+    load_ipam()?;
     let mut ipam = IPAM.lock().unwrap();
     if let Some(subnet) = ipam.get_mut(network) {
         subnet.release_ip(ip);
-        dump_ipam().unwrap();
+        drop(ipam);
+        dump_ipam()?;
     }
+    Ok(())
 }
 
-pub fn register_network_subnet(name: &str, subnet: SubNet) {
-    load_ipam().unwrap();
+pub fn register_network_subnet(name: &str, subnet: SubNet) -> Result<()> {
+    load_ipam()?;
     let mut ipam = IPAM.lock().unwrap();
     ipam.insert(name.to_string(), subnet);
     drop(ipam); // 释放锁
-    dump_ipam().unwrap();
+    dump_ipam()
 }
 
 impl SubNet {
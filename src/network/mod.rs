@@ -2,6 +2,7 @@ mod bridge;
 mod veth;
 mod ipam;
 mod driver;
+#[allow(clippy::module_inception)]
 mod network;
 mod command;
 
@@ -6,14 +6,18 @@ use rtnetlink::{LinkUnspec, new_connection};
 use serde::{Serialize, Deserialize};
 use std::io::Write;
 
+use nix::sched::{setns, CloneFlags};
+
 use crate::{container, network::*};
+use crate::error::{Context, Error, Result};
 use crate::exec::enter_container_netns;
-use log::{error, info};
+use crate::rollback::Rollback;
+use crate::state::network_file;
+use log::info;
 
 // 应该在某个文件里持久化的保存已创建的网络的配置信息，用网络名进行索引
 // TODO：这个 Mutex 似乎不能避免多进程间的数据竞争，只是在单个进程下有效
 static NETWORKS: Mutex<BTreeMap<String, Network>> = Mutex::new(BTreeMap::new());
-pub const NETWORK_FILE: &str = "/root/.mydocker/network/network/network.json";
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Network {
@@ -34,90 +38,153 @@ impl Network {
     }
 }
 
-pub fn dump_network() {
+pub fn dump_network() -> Result<()> {
+    let network_file = network_file();
     let networks = NETWORKS.lock().unwrap();
-    let mut file = std::fs::File::create(NETWORK_FILE).unwrap();
-    serde_json::to_writer(&mut file, &*networks).unwrap();
+    let mut file = std::fs::File::create(&network_file).context(format!("Failed to create {}", network_file))?;
+    serde_json::to_writer(&mut file, &*networks).context(format!("Failed to write {}", network_file))
 }
 
-pub fn load_network() -> std::io::Result<()> {
-    let file = match std::fs::File::open(NETWORK_FILE) {
+pub fn load_network() -> Result<()> {
+    let network_file = network_file();
+    let file = match std::fs::File::open(&network_file) {
         Ok(f) => f,
         Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
             // 文件不存在则创建一个空文件并写入空的 JSON 对象
-            std::fs::create_dir_all(std::path::Path::new(NETWORK_FILE).parent().unwrap())?;
-            let mut f = std::fs::File::create(NETWORK_FILE)?;
-            f.write_all(b"{}")?;
-            std::fs::File::open(NETWORK_FILE)?
+            let dir = std::path::Path::new(&network_file).parent()
+                .ok_or_else(|| Error::Runtime(format!("Invalid network file path {}", network_file)))?;
+            std::fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+            let mut f = std::fs::File::create(&network_file).context(format!("Failed to create {}", network_file))?;
+            f.write_all(b"{}").context(format!("Failed to write {}", network_file))?;
+            std::fs::File::open(&network_file).context(format!("Failed to open {}", network_file))?
         }
-        Err(e) => return Err(e),
+        Err(e) => return Err(e).context(format!("Failed to open {}", network_file)),
     };
     let reader = std::io::BufReader::new(file);
-    let networks: BTreeMap<String, Network> = serde_json::from_reader(reader)?;
+    let networks: BTreeMap<String, Network> = serde_json::from_reader(reader).context(format!("Failed to parse {}", network_file))?;
     *NETWORKS.lock().unwrap() = networks;
     Ok(())
 }
 
-pub fn register_network(network: Network) {
-    load_network().unwrap();
+pub fn register_network(network: Network) -> Result<()> {
+    load_network()?;
     let mut networks = NETWORKS.lock().unwrap();
     networks.insert(network.name.clone(), network);
     drop(networks);
-    dump_network();
+    dump_network()
+}
+
+pub fn network_exists(name: &str) -> Result<bool> {
+    load_network()?;
+    Ok(NETWORKS.lock().unwrap().contains_key(name))
 }
 
 // 在 run 启动容器时，将容器 connect 到指定的网络
-pub fn connect(network_name: &str, container_id: &str) {
-    load_network().unwrap();
+// 分配的 IP 和创建的 veth 都登记到 rollback 中，启动容器的后续步骤失败时一并撤销
+pub fn connect(network_name: &str, container_id: &str, rollback: &mut Rollback) -> Result<()> {
+    load_network()?;
     let networks = NETWORKS.lock().unwrap();
     let drivers = DRIVERS.lock().unwrap();
 
-    let network = networks.get(network_name).unwrap();
-    let driver = drivers.get(&network.driver).unwrap();
-    
-    let ip = allocate_ip(network_name).unwrap();
+    let network = networks.get(network_name).ok_or_else(|| Error::NoSuchNetwork(network_name.to_string()))?;
+    let driver = drivers.get(&network.driver)
+        .ok_or_else(|| Error::Runtime(format!("Unknown network driver {}", network.driver)))?;
+
+    let ip = allocate_ip(network_name)?;
+    let ip_network = network_name.to_string();
+    rollback.push(format!("IP {} in network {}", ip, network_name), move || {
+        release_ip(&ip_network, ip.ip().octets())
+    });
 
     let mut ep = Endpoint {
         id: container_id.to_string(),
         network_name: network_name.to_string(),
-        ip: ip,
+        ip,
+        veth_name: None,
         peer_name: None,
     };
 
     log::info!("In network connect");
-    driver.connect(network_name, &mut ep);
+    // 驱动可能在创建了 veth 之后才失败，所以无论成功与否都登记撤销操作，veth 不存在时 disconnect 什么都不做
+    let connected = driver.connect(network_name, &mut ep);
+    let veth = ep.clone();
+    rollback.push(format!("veth of container {}", container_id), move || disconnect_endpoint(&veth));
+    connected?;
 
     // 到容器的网络命名空间中配置 Endpoint
-    config_endpoint_ip_address_and_route(container_id, &ep);
+    config_endpoint_ip_address_and_route(container_id, &ep)?;
+
+    // 记录 Endpoint，容器退出后据此释放 IP
+    container::record_endpoint(container_id, &ep)
+}
+
+// 容器退出后调用，删除 veth 并把 IP 归还给 IPAM
+pub fn disconnect(container_id: &str) -> Result<()> {
+    let ep = match container::get_endpoint(container_id)? {
+        Some(ep) => ep,
+        None => return Ok(()),
+    };
+    disconnect_endpoint(&ep)?;
+
+    info!("Releasing IP {} of container {}", ep.ip, container_id);
+    release_ip(&ep.network_name, ep.ip.ip().octets())
+}
+
+// 由网络的驱动删除 Endpoint 对应的 veth
+fn disconnect_endpoint(ep: &Endpoint) -> Result<()> {
+    load_network()?;
+    let networks = NETWORKS.lock().unwrap();
+    let drivers = DRIVERS.lock().unwrap();
+
+    if let Some(network) = networks.get(&ep.network_name)
+        && let Some(driver) = drivers.get(&network.driver)
+    {
+        driver.disconnect(ep)?;
+    }
+    Ok(())
 }
 
-fn config_endpoint_ip_address_and_route(container_id: &str, ep: &Endpoint) {
-    let container_pid = container::get_pid(container_id);
-    
-    TOKIO.block_on(async {
+fn config_endpoint_ip_address_and_route(container_id: &str, ep: &Endpoint) -> Result<()> {
+    let container_pid = container::get_pid(container_id)?;
+    let peer_name = ep.peer_name.clone()
+        .ok_or_else(|| Error::Runtime(format!("Endpoint of container {} has no veth peer", container_id)))?;
+
+    let (runtime, handle) = netlink()?;
+    runtime.block_on(async {
         // ip link set veth1 netns ns1
-        HANDLE
+        handle
             .link()
             .set(
-                LinkUnspec::new_with_name(ep.peer_name.as_ref().unwrap())
+                LinkUnspec::new_with_name(&peer_name)
                     .setns_by_pid(container_pid)
                     .build(),
             )
             .execute()
             .await
-            .unwrap();
-    });
-    
-    enter_container_netns(container_id);
+            .context(format!("Failed to move {} into the network namespace of container {}", peer_name, container_id))
+    })?;
 
+    // 记下当前的 network namespace，配置完成后切换回来
+    // 对后台容器而言当前进程是 shim，之后还要在宿主机的 namespace 中清理网络
+    let host_netns = std::fs::File::open("/proc/self/ns/net").context("Failed to open host network namespace")?;
+    enter_container_netns(container_id)?;
+
+    let result = config_in_container_netns(&peer_name, ep);
+
+    // 无论配置是否成功都要回到宿主机的 network namespace
+    setns(host_netns, CloneFlags::CLONE_NEWNET).context("Failed to return to host network namespace")?;
+    result
+}
+
+fn config_in_container_netns(peer_name: &str, ep: &Endpoint) -> Result<()> {
     let tokio_runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
-        .unwrap();
-    let (connection, handle, _) = tokio_runtime.block_on(async { 
-        new_connection().unwrap()
-    });
-    
+        .context("Failed to create tokio runtime")?;
+    let (connection, handle, _) = tokio_runtime.block_on(async {
+        new_connection()
+    }).context("Failed to connect to netlink")?;
+
     tokio_runtime.spawn(connection);
 
     tokio_runtime.block_on(async {
@@ -125,9 +192,11 @@ fn config_endpoint_ip_address_and_route(container_id: &str, ep: &Endpoint) {
         let mut links = handle
             .link()
             .get()
-            .match_name(ep.peer_name.as_ref().unwrap().to_string())
+            .match_name(peer_name.to_string())
             .execute();
-        let link = links.try_next().await.unwrap().unwrap();
+        let link = links.try_next().await
+            .context(format!("Failed to find {}", peer_name))?
+            .ok_or_else(|| Error::Runtime(format!("{} not found in container network namespace", peer_name)))?;
         handle
             .address()
             .add(
@@ -137,14 +206,14 @@ fn config_endpoint_ip_address_and_route(container_id: &str, ep: &Endpoint) {
             )
             .execute()
             .await
-            .unwrap();
+            .context(format!("Failed to add address {} to {}", ep.ip, peer_name))?;
 
         // ip netns exec ns1 ip link set veth1 up
         handle
             .link()
-            .set(LinkUnspec::new_with_name(ep.peer_name.as_ref().unwrap()).up().build())
+            .set(LinkUnspec::new_with_name(peer_name).up().build())
             .execute()
             .await
-            .unwrap();
+            .context(format!("Failed to set {} up", peer_name))
     })
 }
@@ -1,8 +1,12 @@
 use ipnetwork::Ipv4Network;
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Endpoint {
     pub id: String,
     pub network_name: String,
     pub ip: Ipv4Network,
+    #[serde(default)]
+    pub veth_name: Option<String>,  // 在宿主机上、连接到网桥的 Veth 的名称
     pub peer_name: Option<String>,  // 在容器内部的 Veth-peer 的名称
 }
\ No newline at end of file
@@ -0,0 +1,84 @@
+use std::ffi::c_void;
+use std::num::NonZeroUsize;
+use std::ptr::NonNull;
+
+use log::error;
+use nix::errno::Errno;
+use nix::sched::CloneFlags;
+use nix::sys::mman::{mmap_anonymous, mprotect, munmap, MapFlags, ProtFlags};
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::Pid;
+
+use crate::error::{Context, Result, EXIT_RUNTIME_ERROR};
+
+// 子进程的栈大小，不含 guard page
+const STACK_SIZE: usize = 1024 * 1024;
+
+// clone 出的子进程使用的栈，用 mmap 分配在堆上而不是调用者的栈上
+// 最低的一页设为 PROT_NONE 作为 guard page，子进程栈溢出时收到 SIGSEGV，而不是悄悄改写相邻的内存
+struct Stack {
+    base: NonNull<c_void>,
+    len: usize,
+    guard: usize,
+}
+
+impl Stack {
+    fn new(size: usize) -> Result<Self> {
+        let guard = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = size + guard;
+        let base = unsafe {
+            mmap_anonymous(
+                None,
+                NonZeroUsize::new(len).unwrap(),
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_PRIVATE | MapFlags::MAP_STACK,
+            )
+        }.context("Failed to allocate stack for child process")?;
+        let stack = Stack { base, len, guard };
+        unsafe { mprotect(base, guard, ProtFlags::PROT_NONE) }.context("Failed to set up stack guard page")?;
+        Ok(stack)
+    }
+
+    // guard page 之上可用的部分，栈从高地址向低地址增长
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe {
+            let usable = (self.base.as_ptr() as *mut u8).add(self.guard);
+            std::slice::from_raw_parts_mut(usable, self.len - self.guard)
+        }
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { munmap(self.base, self.len) } {
+            error!("Failed to unmap child stack: {}", e);
+        }
+    }
+}
+
+// clone 出一个子进程执行 child，返回子进程的 PID；child 的返回值即子进程的退出码
+// 没有使用 CLONE_VM，子进程拥有自己的一份地址空间，父进程在 clone 返回后就可以释放栈
+// run 用它创建容器的 init 进程，exec 用它在容器的 namespace 中创建新的进程
+pub fn spawn(flags: CloneFlags, mut child: impl FnMut() -> i32) -> Result<Pid> {
+    let mut stack = Stack::new(STACK_SIZE)?;
+    let callback = Box::new(move || child() as isize);
+    unsafe {
+        nix::sched::clone(callback, stack.as_mut_slice(), flags, Some(Signal::SIGCHLD as i32))
+    }.context("clone failed")
+}
+
+// 等待子进程退出，返回 exit code 和导致进程退出的信号，被信号杀死时 exit code 记为 128 + 信号值
+pub fn wait(pid: Pid) -> (i32, Option<Signal>) {
+    loop {
+        match waitpid(pid, None) {
+            Ok(WaitStatus::Exited(_, code)) => return (code, None),
+            Ok(WaitStatus::Signaled(_, signal, _)) => return (128 + signal as i32, Some(signal)),
+            Ok(_) | Err(Errno::EINTR) => continue,
+            Err(e) => {
+                error!("waitpid {} failed: {}", pid, e);
+                return (EXIT_RUNTIME_ERROR, None);
+            }
+        }
+    }
+}
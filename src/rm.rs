@@ -1,19 +1,29 @@
 use crate::RmCommand;
-use crate::container::{delete_metainfo, is_running};
-use crate::run::ROOTFS_BASE_PATH;
+use crate::container::{delete_metainfo, is_running, resolve_container_id};
+use crate::state::rootfs_base_path;
+use crate::error::{Context, Error, Result};
 
-pub fn rm(command: RmCommand) {
-    let container_id = command.container_id.clone();
+pub fn rm(command: RmCommand) -> Result<()> {
+    let container_id = resolve_container_id(&command.container_id)?;
     // 检查容器是否已经在运行
-    if is_running(&container_id) {
-        println!("Container {} is still running", container_id);
-        return;
+    if is_running(&container_id)? {
+        return Err(Error::Conflict(format!("Container {} is still running", container_id)));
     }
 
     // 删除容器的工作目录
-    let overlayfs_path = format!("{}{}/", ROOTFS_BASE_PATH, container_id);
-    std::fs::remove_dir_all(&overlayfs_path).expect("Failed to remove container directory");
+    remove_container_dir(&container_id)?;
 
     // 删除容器元信息
-    delete_metainfo(&container_id);
+    delete_metainfo(&container_id)
+}
+
+// 删除容器在 overlay2 下的目录，目录已经不存在时忽略
+pub fn remove_container_dir(container_id: &str) -> Result<()> {
+    let overlayfs_path = format!("{}{}/", rootfs_base_path(), container_id);
+    match std::fs::remove_dir_all(&overlayfs_path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).context(format!("Failed to remove container directory {}", overlayfs_path))
+        }
+        _ => Ok(()),
+    }
 }
\ No newline at end of file
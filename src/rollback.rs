@@ -0,0 +1,34 @@
+use log::{error, info};
+
+use crate::error::Result;
+
+type Undo<'a> = Box<dyn FnOnce() -> Result<()> + 'a>;
+
+// 创建容器时每完成一步就登记对应的撤销操作
+// 中途出错时 Rollback 随之被 drop，按与创建相反的顺序撤销已经完成的步骤；全部成功后调用 commit 放弃撤销
+#[derive(Default)]
+pub struct Rollback<'a> {
+    steps: Vec<(String, Undo<'a>)>,
+}
+
+impl<'a> Rollback<'a> {
+    pub fn push(&mut self, description: impl Into<String>, undo: impl FnOnce() -> Result<()> + 'a) {
+        self.steps.push((description.into(), Box::new(undo)));
+    }
+
+    pub fn commit(mut self) {
+        self.steps.clear();
+    }
+}
+
+impl Drop for Rollback<'_> {
+    fn drop(&mut self) {
+        // 撤销失败时继续撤销其余的步骤，尽量不留下残余的资源
+        while let Some((description, undo)) = self.steps.pop() {
+            info!("Rolling back {}", description);
+            if let Err(e) = undo() {
+                error!("Failed to roll back {}: {}", description, e);
+            }
+        }
+    }
+}
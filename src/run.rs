@@ -1,100 +1,248 @@
-use libc::{
-    c_void, clone, waitpid, CLONE_NEWIPC, CLONE_NEWNET, CLONE_NEWNS, CLONE_NEWPID, CLONE_NEWUTS, SIGCHLD
-};
 use log::{error, info};
-
-use crate::container::{delete_workspace, gen_id, init_metainfo, init_process, metainfo_exists, new_workspace, record_exit, record_running};
+use nix::sched::CloneFlags;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::waitpid;
+use nix::unistd::Pid;
+use std::net::IpAddr;
+use std::os::fd::AsRawFd;
+
+use crate::container::{
+    capability_mask, container_capabilities, container_env, container_devices, container_mounts, device_rules, validate_devices, validate_mount_options, container_hostname, container_workdir, delete_metainfo, load_image_config, release_container, reserve_container, resolve_env, validate_workdir,
+    ImageConfig, gen_id, get_endpoint, init_metainfo, validate_etc_options, write_etc_files, validate_name, init_process, metainfo_exists, new_workspace,
+    open_pty, proxy_pty, record_exit, record_running, ChildSync, ContainerStdio, Device, ExitStatus, MountSpec, PendingMounts, SyncPipe,
+    UserNamespace,
+};
+use crate::error::{Context, Error, Result, EXIT_RUNTIME_ERROR};
+use crate::process::spawn;
+use crate::rollback::Rollback;
+use crate::shim::{spawn_shim, supervise};
 use crate::{network, RunCommand};
 use crate::cgroupsv2::{CGroupManager, ResourceConfig};
+use crate::state::is_rootless;
+use crate::seccomp::{seccomp_filter, validate_security_opts, SeccompFilter};
 
-pub const IMAGE_BASE_PATH: &str = "/root/.mydocker/image/";         // 镜像存储路径
-pub const ROOTFS_BASE_PATH: &str = "/root/.mydocker/overlay2/";     // 镜像以 OverlayFS 的形式 mount 的位置
 
 pub struct RunArg {
     pub container_id: String,
     pub command: String,
     pub args: Vec<String>,
-    pub detach: bool,
+    pub stdio: Option<ContainerStdio>,  // 为 None 时直接继承 mydocker 的标准输入输出
+    pub sync: ChildSync,
+    pub userns: bool,                   // 容器运行在新的 user namespace 中
+    pub hostname: String,
+    pub env: Vec<String>,
+    pub workdir: String,
+    pub user: Option<String>,           // -u 指定的 user[:group]，在容器的 /etc/passwd 中解析
+    pub capabilities: u64,              // execvp 之前保留的 capability
+    pub seccomp: Option<SeccompFilter>, // 为 None 时不过滤系统调用
+    pub mounts: PendingMounts,
+    pub mount_specs: Vec<MountSpec>,    // proc、sys、dev 以及 --tmpfs
+    pub devices: Vec<Device>,           // /dev 中的设备节点
+    pub read_only: bool,
+    pub privileged: bool,               // 为 true 时不屏蔽 /proc 和 /sys 中的敏感路径
 }
 
 impl RunArg {
-    fn new(container_id: &str, command: &str, args: Vec<String>, detach: bool) -> Self {
-        RunArg {
+    fn new(
+        command: &RunCommand,
+        container_id: &str,
+        image: &ImageConfig,
+        stdio: Option<ContainerStdio>,
+        sync: ChildSync,
+        mounts: PendingMounts,
+    ) -> Result<Self> {
+        let capabilities = container_capabilities(command)?;
+        Ok(RunArg {
             container_id: container_id.to_string(),
-            command: command.to_string(),
-            args: args,
-            detach,
-        }
+            command: command.command.clone(),
+            args: command.args.clone(),
+            stdio,
+            sync,
+            userns: use_userns(command),
+            hostname: container_hostname(container_id, command),
+            env: container_env(container_id, command, image),
+            workdir: container_workdir(command.workdir.as_deref(), image)?,
+            user: command.user.clone(),
+            capabilities: capability_mask(&capabilities),
+            seccomp: seccomp_filter(command, &capabilities)?,
+            mounts,
+            mount_specs: container_mounts(command, use_userns(command))?,
+            devices: container_devices(command)?,
+            read_only: command.read_only,
+            privileged: command.privileged,
+        })
     }
     
 }
 
-pub fn run(command: RunCommand) {
-    let container_id = gen_id();
-    run_container(command, container_id);
+// rootless 模式下容器总是运行在新的 user namespace 中
+pub fn use_userns(command: &RunCommand) -> bool {
+    command.userns || is_rootless()
 }
 
-pub fn run_container(command: RunCommand, container_id: String) {
-    let run_arg = Box::new(RunArg::new(&container_id, &command.command, command.args.clone(), command.detach));
-
-    const STACK_SIZE: usize = 1024 * 1024;
-    let mut stack = [0; STACK_SIZE];
+pub fn run(mut command: RunCommand) -> Result<i32> {
+    if let Some(name) = &command.name {
+        validate_name(name)?;
+    }
+    validate_etc_options(&command)?;
+    container_capabilities(&command)?;
+    validate_security_opts(&command)?;
+    validate_mount_options(&command)?;
+    validate_devices(&command)?;
+    if let Some(workdir) = &command.workdir {
+        validate_workdir(workdir)?;
+    }
+    // env-file 在创建容器时读取一次，合并到 env 中保存，重新启动容器时不再读取
+    command.env = resolve_env(&command.env, &command.env_file)?;
+    command.env_file.clear();
+    // 普通用户不能创建 veth 和 bridge
+    if is_rootless() && command.net.is_some() {
+        return Err(Error::InvalidArgument("Networks are not supported in rootless mode".to_string()));
+    }
+    let container_id = gen_id();
+    run_container(command, container_id)
+}
 
-    let volume: Option<&str> = command.volume.as_deref(); // 获取 volume 的值
-    new_workspace(&container_id, &command.image, volume); // 创建 overlayfs 的工作空间，mount volumn 目录
-
-    let flags = CLONE_NEWPID | CLONE_NEWNS | CLONE_NEWUTS | CLONE_NEWNET | CLONE_NEWIPC | SIGCHLD;
-    let ret;
-    unsafe {
-        /*
-         * On success, the thread ID of the child process is returned in the
-         * caller's thread of execution.  On failure, -1 is returned in the
-         * caller's context, no child process is created, and errno is set to
-         * indicate the error. [linux man7.org]
-         */
-
-        ret = clone(init_process,   // 使用 libc 中 clone 创建子进程并将子进程放入新的 namespace
-            stack.as_mut_ptr().add(STACK_SIZE) as *mut c_void,
-            flags,
-            Box::into_raw(run_arg) as *mut c_void,
-        );
-        if ret == -1 {
-            error!("Error: clone failed");
-        }
+// 返回 mydocker 的退出码：前台运行时为容器的退出码，后台运行时为 0
+pub fn run_container(command: RunCommand, container_id: String) -> Result<i32> {
+    // 后台运行的容器交给 shim 进程管理，当前进程等 shim 启动容器后即退出
+    if command.detach {
+        spawn_shim(command, container_id)?;
+        return Ok(0);
     }
 
-    if !metainfo_exists(&container_id) {
-        init_metainfo(&container_id, ret as u32, command.clone()); // 初始化容器的元信息
+    // -t 时由 mydocker 分配 pty，slave 作为容器的控制终端，master 由当前进程代理到用户终端
+    let pty = if command.tty {
+        Some(open_pty().context("Failed to allocate pty")?)
     } else {
-        record_running(&container_id, ret as u32); // 记录容器的运行状态
+        None
+    };
+    let stdio = pty.as_ref().map(|(_, slave)| ContainerStdio::Pty(slave.as_raw_fd()));
+    let pid = create_container(&command, &container_id, stdio)?;
+
+    // 子进程已经持有 slave 的副本，父进程只保留 master
+    let pty_master = pty.map(|(master, _slave)| master);
+    // 无法代理终端时容器已经在运行，仍然要等待它退出并记录退出状态
+    if let Some(master) = &pty_master
+        && let Err(e) = proxy_pty(master, command.interactive)
+    {
+        error!("{}", e);
     }
 
-    // let run_arg = RunArg::new(command);
-    let cgroupv2_manager = CGroupManager::new(container_id.clone());
-    cgroupv2_manager.create_cgroup();
-    cgroupv2_manager.set(ResourceConfig {
+    // 前台运行时由当前进程充当容器的 supervisor
+    Ok(supervise(&container_id, pid, &command))
+}
+
+fn resource_config(command: &RunCommand) -> Result<ResourceConfig> {
+    Ok(ResourceConfig {
         cpu: command.cpu,
-        memory: command.mem,    // Rust 允许单独移动结构体某个字段的所有权，只要之后不再使用这个字段。
-    });
-    cgroupv2_manager.add_process(ret as u32); // 将子进程添加到 cgroup 中
+        memory: command.mem.clone(),
+        cpuset_cpus: command.cpuset_cpus.clone(),
+        cpuset_mems: command.cpuset_mems.clone(),
+        device_read_bps: command.device_read_bps.clone(),
+        device_write_bps: command.device_write_bps.clone(),
+        blkio_weight: command.blkio_weight,
+        pids: command.pids_limit,
+        hugetlb: command.hugetlb_limit.clone(),
+        devices: device_rules(command)?,
+    })
+}
 
-    if let Some(network) = command.net {
-        info!("Connecting container {} to network {}", container_id, network);
-        network::connect(&network, &container_id);
+// 创建工作空间，clone 出容器进程并完成元信息、cgroup 和网络的配置，返回容器进程的 PID
+// 每一步都登记了撤销操作，任何一步失败都会按相反的顺序撤销已经完成的步骤
+// 容器进程在所有配置完成之前阻塞在同步通道上，返回时已经成功 execvp
+pub fn create_container(command: &RunCommand, container_id: &str, stdio: Option<ContainerStdio>) -> Result<i32> {
+    let cgroupv2_manager = CGroupManager::new(container_id.to_string());
+    let mut rollback = Rollback::default();
+
+    // 新建的容器先占用 ID 和名字，同时以相同的名字创建的容器只有一个能继续
+    if !metainfo_exists(container_id) {
+        reserve_container(container_id, command.name.as_deref())?;
+        rollback.push(format!("reservation of container {}", container_id), || {
+            release_container(container_id, command.name.as_deref())
+        });
     }
 
-    if command.detach {
-        return ;
+    // rootless 模式下只有 systemd 委派了 cgroup 时才能限制资源
+    let use_cgroup = cgroupv2_manager.is_available();
+    let resource_config = resource_config(command)?;
+    if !use_cgroup && !cgroupv2_manager.controllers(&resource_config).is_empty() {
+        return Err(Error::InvalidArgument("Resource limits require a delegated cgroup in rootless mode".to_string()));
     }
-    
-    unsafe {
-        waitpid(ret, std::ptr::null_mut(), 0); // 等待子进程/容器进程结束
+    let userns = if use_userns(command) { Some(UserNamespace::new()?) } else { None };
+    let image = load_image_config(&command.image)?;
+
+    let volume: Option<&str> = command.volume.as_deref(); // 获取 volume 的值
+    // 创建 overlayfs 的工作空间，mount volumn 目录
+    let mounts = new_workspace(container_id, &command.image, volume, userns.as_ref(), &mut rollback)?;
+
+    // 先创建 cgroup，撤销时容器进程已经被杀死，cgroup 才能删除
+    if use_cgroup {
+        cgroupv2_manager.create_cgroup()?;
+        rollback.push(format!("cgroup {}", container_id), || cgroupv2_manager.destroy_cgroup());
+        cgroupv2_manager.set(resource_config)?;
+    }
+
+    let sync = SyncPipe::new()?;
+    let pid = clone_container(command, container_id, &image, stdio, sync.child_end(), mounts)?;
+    let mut sync = sync.into_parent();
+    rollback.push(format!("container process {}", pid), move || {
+        let _ = kill(Pid::from_raw(pid), Signal::SIGKILL);
+        waitpid(Pid::from_raw(pid), None).context(format!("Failed to wait for container process {}", pid))?;
+        Ok(())
+    });
+    // init 进程切换为容器中的 root 之前必须写好 ID 映射
+    if let Some(userns) = &userns {
+        userns.write_maps(Pid::from_raw(pid))?;
+    }
+    if use_cgroup {
+        cgroupv2_manager.add_process(pid as u32)?; // 将子进程添加到 cgroup 中
     }
 
-    cgroupv2_manager.check_cgroup_memory_events(); // 检查 cgroup 内存事件
-    cgroupv2_manager.destroy_cgroup();
+    // 新建的容器撤销时删除元信息，重新启动的容器撤销时记录为以 125 退出
+    if !metainfo_exists(container_id) {
+        // 初始化容器的元信息
+        init_metainfo(container_id, pid as u32, command.clone(), container_capabilities(command)?)?;
+        rollback.push(format!("metainfo of container {}", container_id), || delete_metainfo(container_id));
+    } else {
+        record_running(container_id, pid as u32)?; // 记录容器的运行状态
+        rollback.push(format!("running state of container {}", container_id), || {
+            record_exit(container_id, ExitStatus { code: EXIT_RUNTIME_ERROR, signal: None, oom_killed: false })
+        });
+    }
+
+    if let Some(network) = &command.net {
+        info!("Connecting container {} to network {}", container_id, network);
+        network::connect(network, container_id, &mut rollback)?;
+    }
+    // 容器的 IP 地址在连接网络之后才能确定，生成的文件由 init 进程在 pivot_root 之前绑定挂载
+    let ip = get_endpoint(container_id)?.map(|endpoint| IpAddr::V4(endpoint.ip.ip()));
+    write_etc_files(container_id, command, ip)?;
+
+    // 通知容器进程继续执行，mount 或 execvp 失败时同样撤销所有步骤
+    sync.resume()?;
+    sync.wait_exec()?;
 
-    delete_workspace(&container_id, volume); // 删除 overlayfs 的工作空间
+    rollback.commit();
+    Ok(pid)
+}
 
-    record_exit(&container_id); // 记录容器的退出状态
+fn clone_container(
+    command: &RunCommand,
+    container_id: &str,
+    image: &ImageConfig,
+    stdio: Option<ContainerStdio>,
+    sync: ChildSync,
+    mounts: PendingMounts,
+) -> Result<i32> {
+    let run_arg = RunArg::new(command, container_id, image, stdio, sync, mounts)?;
+    // 创建子进程并将子进程放入新的 namespace
+    let mut flags = CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWUTS |
+        CloneFlags::CLONE_NEWNET | CloneFlags::CLONE_NEWIPC;
+    // 其余的 namespace 都属于新的 user namespace，init 进程在其中拥有完整的 capabilities
+    if run_arg.userns {
+        flags |= CloneFlags::CLONE_NEWUSER;
+    }
+    let pid = spawn(flags, || init_process(&run_arg))?;
+    Ok(pid.as_raw())
 }
@@ -0,0 +1,302 @@
+use libc::{
+    sock_filter, BPF_ABS, BPF_ALU, BPF_AND, BPF_JA, BPF_JEQ, BPF_JGE, BPF_JGT, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W,
+};
+
+use super::profile::{Action, CmpOp};
+use crate::error::{Error, Result};
+
+// struct seccomp_data 中各字段的偏移
+const OFFSET_NR: u32 = 0;
+const OFFSET_ARCH: u32 = 4;
+const OFFSET_ARGS: u32 = 16;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc00000b7;
+
+// x32 ABI 的系统调用编号带有这一位，在 x86_64 的过滤器中一律拒绝
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x40000000;
+
+// 跳转到当前规则末尾（即下一条规则）的占位符，规则生成完毕后回填
+const JUMP_TO_NEXT_RULE: u32 = u32::MAX;
+
+// 一条规则：系统调用的所有参数条件都满足时返回 action
+pub struct Rule {
+    pub args: Vec<(u32, CmpOp, u64, u64)>,  // 参数下标、比较方式、value、value_two
+    pub action: Action,
+}
+
+fn stmt(code: u32, k: u32) -> sock_filter {
+    sock_filter { code: code as u16, jt: 0, jf: 0, k }
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter { code: code as u16, jt, jf, k }
+}
+
+fn ret(action: Action) -> sock_filter {
+    let k = match action {
+        Action::Allow => libc::SECCOMP_RET_ALLOW,
+        Action::Errno(errno) => libc::SECCOMP_RET_ERRNO | (errno & libc::SECCOMP_RET_DATA),
+        Action::KillThread => libc::SECCOMP_RET_KILL_THREAD,
+        Action::KillProcess => libc::SECCOMP_RET_KILL_PROCESS,
+        Action::Trap => libc::SECCOMP_RET_TRAP,
+        Action::Trace(data) => libc::SECCOMP_RET_TRACE | (data & libc::SECCOMP_RET_DATA),
+        Action::Log => libc::SECCOMP_RET_LOG,
+    };
+    stmt(BPF_RET | BPF_K, k)
+}
+
+fn load(offset: u32) -> sock_filter {
+    stmt(BPF_LD | BPF_W | BPF_ABS, offset)
+}
+
+// 把 64 位的参数比较展开为对高低 32 位的比较（小端序，低 32 位在前）
+// 条件满足时顺序执行到下一条指令，不满足时跳到规则末尾；每段末尾的 ja 即失败出口
+fn compile_arg(index: u32, op: CmpOp, value: u64, value_two: u64) -> Vec<sock_filter> {
+    let lo = OFFSET_ARGS + index * 8;
+    let hi = lo + 4;
+    let (value_hi, value_lo) = ((value >> 32) as u32, value as u32);
+    let fail = jump(BPF_JMP | BPF_JA, JUMP_TO_NEXT_RULE, 0, 0);
+    match op {
+        CmpOp::Eq => vec![
+            load(hi),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, value_hi, 0, 2),
+            load(lo),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, value_lo, 1, 0),
+            fail,
+        ],
+        CmpOp::Ne => vec![
+            load(hi),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, value_hi, 0, 3),
+            load(lo),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, value_lo, 0, 1),
+            fail,
+        ],
+        CmpOp::MaskedEq => {
+            let (datum_hi, datum_lo) = ((value_two >> 32) as u32, value_two as u32);
+            vec![
+                load(hi),
+                stmt(BPF_ALU | BPF_AND | BPF_K, value_hi),
+                jump(BPF_JMP | BPF_JEQ | BPF_K, datum_hi, 0, 3),
+                load(lo),
+                stmt(BPF_ALU | BPF_AND | BPF_K, value_lo),
+                jump(BPF_JMP | BPF_JEQ | BPF_K, datum_lo, 1, 0),
+                fail,
+            ]
+        }
+        // 高 32 位大于时成立，等于时再比较低 32 位
+        CmpOp::Gt | CmpOp::Ge => {
+            let low_cmp = if matches!(op, CmpOp::Gt) { BPF_JGT } else { BPF_JGE };
+            vec![
+                load(hi),
+                jump(BPF_JMP | BPF_JGT | BPF_K, value_hi, 4, 0),
+                jump(BPF_JMP | BPF_JEQ | BPF_K, value_hi, 0, 2),
+                load(lo),
+                jump(BPF_JMP | low_cmp | BPF_K, value_lo, 1, 0),
+                fail,
+            ]
+        }
+        // BPF 没有小于的跳转，用大于等于并交换跳转目标
+        CmpOp::Lt | CmpOp::Le => {
+            let low_cmp = if matches!(op, CmpOp::Lt) { BPF_JGE } else { BPF_JGT };
+            vec![
+                load(hi),
+                jump(BPF_JMP | BPF_JGE | BPF_K, value_hi, 0, 4),
+                jump(BPF_JMP | BPF_JEQ | BPF_K, value_hi, 0, 2),
+                load(lo),
+                jump(BPF_JMP | low_cmp | BPF_K, value_lo, 0, 1),
+                fail,
+            ]
+        }
+    }
+}
+
+fn compile_rule(rule: &Rule) -> Vec<sock_filter> {
+    let mut insns: Vec<sock_filter> = rule.args.iter()
+        .flat_map(|&(index, op, value, value_two)| compile_arg(index, op, value, value_two))
+        .collect();
+    insns.push(ret(rule.action));
+    // 回填失败出口：跳过本条规则余下的指令
+    let len = insns.len();
+    for (i, insn) in insns.iter_mut().enumerate() {
+        if insn.code == (BPF_JMP | BPF_JA) as u16 && insn.k == JUMP_TO_NEXT_RULE {
+            insn.k = (len - i - 1) as u32;
+        }
+    }
+    insns
+}
+
+// 生成的过滤器：先检查架构，再按系统调用编号逐个匹配，每个系统调用的规则按配置文件中的顺序依次尝试
+// 某个系统调用的规则都不满足、或者系统调用没有规则时返回默认动作
+pub fn compile(syscalls: &[(u32, Vec<Rule>)], default_action: Action) -> Result<Vec<sock_filter>> {
+    // 只认识本机架构的系统调用编号，32 位程序（i386、x32）的系统调用与 docker 对未列出的系统调用一样返回默认动作
+    // 默认动作为允许时改为 EPERM，否则可以通过编号不同的 32 位系统调用绕过配置中禁止的规则
+    let foreign_action = match default_action {
+        Action::Allow | Action::Log => Action::Errno(libc::EPERM as u32),
+        action => action,
+    };
+    let mut insns = vec![
+        load(OFFSET_ARCH),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+        ret(foreign_action),
+        load(OFFSET_NR),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    insns.extend([
+        jump(BPF_JMP | BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1),
+        ret(foreign_action),
+    ]);
+
+    for (nr, rules) in syscalls {
+        // 没有参数条件的规则之后的规则不会被执行到
+        let reachable = rules.iter().position(|rule| rule.args.is_empty()).map_or(rules.len(), |i| i + 1);
+        let rules = &rules[..reachable];
+        if let [rule] = rules
+            && rule.args.is_empty()
+        {
+            insns.push(jump(BPF_JMP | BPF_JEQ | BPF_K, *nr, 0, 1));
+            insns.push(ret(rule.action));
+            continue;
+        }
+
+        let mut body: Vec<sock_filter> = rules.iter().flat_map(compile_rule).collect();
+        body.push(ret(default_action));
+        insns.push(jump(BPF_JMP | BPF_JEQ | BPF_K, *nr, 1, 0));
+        insns.push(jump(BPF_JMP | BPF_JA, body.len() as u32, 0, 0));
+        insns.extend(body);
+    }
+    insns.push(ret(default_action));
+
+    if insns.len() > libc::BPF_MAXINSNS as usize {
+        return Err(Error::InvalidArgument(format!(
+            "Seccomp profile is too large: {} instructions, at most {}", insns.len(), libc::BPF_MAXINSNS
+        )));
+    }
+    Ok(insns)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::seccomp::profile::{Action, CmpOp};
+
+    // 解释执行生成的过滤器，返回 SECCOMP_RET_* 的值；只支持生成器用到的指令
+    pub fn run(filter: &[sock_filter], arch: u32, nr: u32, args: [u64; 6]) -> u32 {
+        let mut data = vec![];
+        data.extend(nr.to_ne_bytes());
+        data.extend(arch.to_ne_bytes());
+        data.extend(0u64.to_ne_bytes());
+        for arg in args {
+            data.extend(arg.to_ne_bytes());
+        }
+        let (mut pc, mut acc) = (0usize, 0u32);
+        loop {
+            let insn = filter[pc];
+            let code = insn.code as u32;
+            pc += 1;
+            if code == BPF_LD | BPF_W | BPF_ABS {
+                let offset = insn.k as usize;
+                acc = u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+            } else if code == BPF_ALU | BPF_AND | BPF_K {
+                acc &= insn.k;
+            } else if code == BPF_JMP | BPF_JA {
+                pc += insn.k as usize;
+            } else if code == BPF_RET | BPF_K {
+                return insn.k;
+            } else {
+                let taken = match code & !(BPF_JMP | BPF_K) {
+                    BPF_JEQ => acc == insn.k,
+                    BPF_JGT => acc > insn.k,
+                    BPF_JGE => acc >= insn.k,
+                    _ => panic!("unsupported instruction {:#x}", code),
+                };
+                pc += if taken { insn.jt } else { insn.jf } as usize;
+            }
+        }
+    }
+
+    const EPERM: u32 = libc::SECCOMP_RET_ERRNO | 1;
+    const ALLOW: u32 = libc::SECCOMP_RET_ALLOW;
+
+    fn rule(args: Vec<(u32, CmpOp, u64, u64)>, action: Action) -> Rule {
+        Rule { args, action }
+    }
+
+    #[test]
+    fn matches_syscall_numbers() {
+        let filter = compile(&[(1, vec![rule(vec![], Action::Allow)])], Action::Errno(1)).unwrap();
+        assert_eq!(run(&filter, AUDIT_ARCH, 1, [0; 6]), ALLOW);
+        assert_eq!(run(&filter, AUDIT_ARCH, 2, [0; 6]), EPERM);
+    }
+
+    #[test]
+    fn foreign_arch_uses_default_action() {
+        let filter = compile(&[(1, vec![rule(vec![], Action::Allow)])], Action::Errno(38)).unwrap();
+        assert_eq!(run(&filter, 0x40000003, 1, [0; 6]), libc::SECCOMP_RET_ERRNO | 38);
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(run(&filter, AUDIT_ARCH, X32_SYSCALL_BIT | 1, [0; 6]), libc::SECCOMP_RET_ERRNO | 38);
+
+        // 默认允许时其他架构的系统调用不能绕过规则
+        let filter = compile(&[(1, vec![rule(vec![], Action::Errno(1))])], Action::Allow).unwrap();
+        assert_eq!(run(&filter, 0x40000003, 2, [0; 6]), EPERM);
+        assert_eq!(run(&filter, AUDIT_ARCH, 2, [0; 6]), ALLOW);
+    }
+
+    #[test]
+    fn rules_are_tried_in_order() {
+        let rules = vec![
+            rule(vec![(0, CmpOp::Eq, 7, 0)], Action::Allow),
+            rule(vec![(1, CmpOp::Eq, 8, 0)], Action::Errno(5)),
+        ];
+        let filter = compile(&[(1, rules)], Action::Errno(1)).unwrap();
+        assert_eq!(run(&filter, AUDIT_ARCH, 1, [7, 8, 0, 0, 0, 0]), ALLOW);
+        assert_eq!(run(&filter, AUDIT_ARCH, 1, [0, 8, 0, 0, 0, 0]), libc::SECCOMP_RET_ERRNO | 5);
+        assert_eq!(run(&filter, AUDIT_ARCH, 1, [0, 0, 0, 0, 0, 0]), EPERM);
+        // 其他系统调用不受影响
+        assert_eq!(run(&filter, AUDIT_ARCH, 2, [7, 8, 0, 0, 0, 0]), EPERM);
+    }
+
+    // 用跨越高低 32 位的值检查每种比较
+    #[test]
+    fn compares_64_bit_arguments() {
+        let value = 0x1_0000_0005;
+        let cases: [(CmpOp, &[(u64, bool)]); 6] = [
+            (CmpOp::Eq, &[(value, true), (5, false), (0x2_0000_0005, false)]),
+            (CmpOp::Ne, &[(value, false), (5, true), (0x1_0000_0006, true)]),
+            (CmpOp::Gt, &[(value + 1, true), (value, false), (0x2_0000_0000, true), (0xffff_ffff, false)]),
+            (CmpOp::Ge, &[(value, true), (value - 1, false), (0x2_0000_0000, true)]),
+            (CmpOp::Lt, &[(value - 1, true), (value, false), (0xffff_ffff, true), (0x2_0000_0000, false)]),
+            (CmpOp::Le, &[(value, true), (value + 1, false), (3, true)]),
+        ];
+        for (op, inputs) in cases {
+            let filter = compile(&[(1, vec![rule(vec![(2, op, value, 0)], Action::Allow)])], Action::Errno(1)).unwrap();
+            for &(arg, allowed) in inputs {
+                let result = run(&filter, AUDIT_ARCH, 1, [0, 0, arg, 0, 0, 0]);
+                assert_eq!(result == ALLOW, allowed, "arg {:#x}", arg);
+            }
+        }
+    }
+
+    #[test]
+    fn compares_masked_arguments() {
+        // clone 的 flags 中不能有 CLONE_NEWUSER 这类规则
+        let mask = libc::CLONE_NEWUSER as u64;
+        let filter = compile(&[(1, vec![rule(vec![(0, CmpOp::MaskedEq, mask, 0)], Action::Allow)])], Action::Errno(1)).unwrap();
+        assert_eq!(run(&filter, AUDIT_ARCH, 1, [libc::CLONE_VM as u64, 0, 0, 0, 0, 0]), ALLOW);
+        assert_eq!(run(&filter, AUDIT_ARCH, 1, [mask | libc::CLONE_VM as u64, 0, 0, 0, 0, 0]), EPERM);
+        let filter = compile(&[(1, vec![rule(vec![(0, CmpOp::MaskedEq, 0xff_0000_00ff, 0x12_0000_0034)], Action::Allow)])], Action::Errno(1)).unwrap();
+        assert_eq!(run(&filter, AUDIT_ARCH, 1, [0x12_ffff_ff34, 0, 0, 0, 0, 0]), ALLOW);
+        assert_eq!(run(&filter, AUDIT_ARCH, 1, [0x13_0000_0034, 0, 0, 0, 0, 0]), EPERM);
+    }
+
+    #[test]
+    fn rejects_oversized_filters() {
+        let syscalls: Vec<(u32, Vec<Rule>)> = (0..3000)
+            .map(|nr| (nr, vec![rule(vec![(0, CmpOp::Eq, 1, 0)], Action::Allow)]))
+            .collect();
+        assert!(compile(&syscalls, Action::Errno(1)).is_err());
+    }
+}
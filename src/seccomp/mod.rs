@@ -0,0 +1,177 @@
+mod bpf;
+mod profile;
+mod syscalls;
+
+use libc::{sock_filter, sock_fprog, syscall, SYS_seccomp, SECCOMP_SET_MODE_FILTER};
+use log::{debug, warn};
+use nix::errno::Errno;
+
+use crate::RunCommand;
+use crate::error::{Context, Error, Result};
+use bpf::Rule;
+use profile::{parse_kernel, Action, CmpOp, Profile};
+use syscalls::{is_foreign_syscall, syscall_number};
+
+// docker 默认的 seccomp 配置，禁止 mount、kexec_load、bpf 等会影响宿主机的系统调用
+const DEFAULT_PROFILE: &str = include_str!("default.json");
+const UNCONFINED: &str = "unconfined";
+
+// 配置文件中使用 docker 的架构名
+#[cfg(target_arch = "x86_64")]
+const NATIVE_ARCH: &str = "amd64";
+#[cfg(target_arch = "aarch64")]
+const NATIVE_ARCH: &str = "arm64";
+
+// 编译好的 BPF 过滤器，在父进程中生成，容器进程 execvp 之前安装
+pub struct SeccompFilter(Vec<sock_filter>);
+
+impl SeccompFilter {
+    // 安装之后过滤器对当前进程和 execvp 的程序一直有效，无法撤销
+    pub fn install(&self) -> Result<()> {
+        let prog = sock_fprog { len: self.0.len() as u16, filter: self.0.as_ptr() as *mut sock_filter };
+        if unsafe { syscall(SYS_seccomp, SECCOMP_SET_MODE_FILTER, 0, &prog) } == 0 {
+            return Ok(());
+        }
+        // 没有 CAP_SYS_ADMIN 时必须先设置 no_new_privs
+        if Errno::last() != Errno::EACCES {
+            return Err(Errno::last()).context("Failed to install seccomp filter");
+        }
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } < 0 {
+            return Err(Errno::last()).context("Failed to set no_new_privs");
+        }
+        if unsafe { syscall(SYS_seccomp, SECCOMP_SET_MODE_FILTER, 0, &prog) } < 0 {
+            return Err(Errno::last()).context("Failed to install seccomp filter");
+        }
+        Ok(())
+    }
+}
+
+// 检查 --security-opt，目前只支持 seccomp=<file|unconfined>
+pub fn validate_security_opts(command: &RunCommand) -> Result<()> {
+    seccomp_profile(command).map(|_| ())
+}
+
+// 返回 --security-opt 指定的 seccomp 配置文件，None 表示不过滤；没有指定时使用默认配置
+fn seccomp_profile(command: &RunCommand) -> Result<Option<String>> {
+    let mut profile = Some(DEFAULT_PROFILE.to_string());
+    for opt in &command.security_opt {
+        // docker 同时接受 seccomp=file 和 seccomp:file
+        let value = match opt.split_once(['=', ':']) {
+            Some(("seccomp", value)) if !value.is_empty() => value,
+            _ => return Err(Error::InvalidArgument(format!("Invalid --security-opt {}", opt))),
+        };
+        profile = match value {
+            UNCONFINED => None,
+            path => Some(std::fs::read_to_string(path).context(format!("Failed to read seccomp profile {}", path))?),
+        };
+    }
+    Ok(profile)
+}
+
+// 根据 --security-opt 和容器保留的 capability 生成过滤器，--privileged 时与 docker 一样不过滤
+// 配置文件中依赖 capability 的规则（如 CAP_SYS_ADMIN 允许 mount）只在容器保留了对应的 capability 时生效
+pub fn seccomp_filter(command: &RunCommand, capabilities: &[String]) -> Result<Option<SeccompFilter>> {
+    if command.privileged {
+        return Ok(None);
+    }
+    let Some(content) = seccomp_profile(command)? else {
+        return Ok(None);
+    };
+    let profile: Profile = serde_json::from_str(&content)
+        .map_err(|e| Error::InvalidArgument(format!("Invalid seccomp profile: {}", e)))?;
+    compile_profile(&profile, capabilities).map(Some)
+}
+
+fn compile_profile(profile: &Profile, capabilities: &[String]) -> Result<SeccompFilter> {
+    let default_action = Action::parse(&profile.default_action, profile.default_errno_ret)?;
+    let kernel = std::fs::read_to_string("/proc/sys/kernel/osrelease").ok()
+        .and_then(|release| parse_kernel(release.trim()))
+        .unwrap_or((0, 0));
+
+    // 按系统调用编号分组，保留配置文件中的顺序
+    let mut syscalls: Vec<(u32, Vec<Rule>)> = vec![];
+    for rule in &profile.syscalls {
+        if !rule.applies(capabilities, NATIVE_ARCH, kernel) {
+            continue;
+        }
+        let action = Action::parse(&rule.action, rule.errno_ret.or(profile.default_errno_ret))?;
+        if let Some(arg) = rule.args.iter().find(|arg| arg.index > 5) {
+            return Err(Error::InvalidArgument(format!("Invalid seccomp argument index {}", arg.index)));
+        }
+        let args = rule.args.iter()
+            .map(|arg| Ok((arg.index, CmpOp::parse(&arg.op)?, arg.value, arg.value_two)))
+            .collect::<Result<Vec<_>>>()?;
+        for name in rule.syscall_names() {
+            // 配置文件中包含其他架构才有的系统调用，直接忽略；表中没有的系统调用无法过滤，打印警告
+            let Some(nr) = syscall_number(name) else {
+                if is_foreign_syscall(name) {
+                    debug!("Syscall {} is not available on this architecture, ignored", name);
+                } else {
+                    warn!("Unknown syscall {} in seccomp profile, ignored", name);
+                }
+                continue;
+            };
+            let rule = Rule { args: args.clone(), action };
+            match syscalls.iter_mut().find(|(n, _)| *n == nr) {
+                Some((_, rules)) => rules.push(rule),
+                None => syscalls.push((nr, vec![rule])),
+            }
+        }
+    }
+    bpf::compile(&syscalls, default_action).map(SeccompFilter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bpf::tests::run;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc00000b7;
+
+    fn default_filter(capabilities: &[&str]) -> SeccompFilter {
+        let profile: Profile = serde_json::from_str(DEFAULT_PROFILE).unwrap();
+        let capabilities: Vec<String> = capabilities.iter().map(|cap| cap.to_string()).collect();
+        compile_profile(&profile, &capabilities).unwrap()
+    }
+
+    fn result(filter: &SeccompFilter, name: &str, args: [u64; 6]) -> u32 {
+        run(&filter.0, AUDIT_ARCH, syscall_number(name).unwrap(), args)
+    }
+
+    #[test]
+    fn default_profile_depends_on_capabilities() {
+        let filter = default_filter(&[]);
+        assert_eq!(result(&filter, "read", [0; 6]), libc::SECCOMP_RET_ALLOW);
+        assert_eq!(result(&filter, "io_pgetevents", [0; 6]), libc::SECCOMP_RET_ALLOW);
+        assert_eq!(result(&filter, "mount", [0; 6]), libc::SECCOMP_RET_ERRNO | libc::EPERM as u32);
+        assert_eq!(result(&filter, "kexec_load", [0; 6]), libc::SECCOMP_RET_ERRNO | libc::EPERM as u32);
+
+        let filter = default_filter(&["CAP_SYS_ADMIN"]);
+        assert_eq!(result(&filter, "mount", [0; 6]), libc::SECCOMP_RET_ALLOW);
+    }
+
+    #[test]
+    fn default_profile_restricts_arguments() {
+        let filter = default_filter(&[]);
+        // personality 只允许几个固定的值
+        assert_eq!(result(&filter, "personality", [0, 0, 0, 0, 0, 0]), libc::SECCOMP_RET_ALLOW);
+        assert_ne!(result(&filter, "personality", [0x0040000, 0, 0, 0, 0, 0]), libc::SECCOMP_RET_ALLOW);
+        // 没有 CAP_SYS_ADMIN 时 clone 不能创建新的 namespace
+        assert_eq!(result(&filter, "clone", [libc::SIGCHLD as u64, 0, 0, 0, 0, 0]), libc::SECCOMP_RET_ALLOW);
+        assert_ne!(result(&filter, "clone", [libc::CLONE_NEWNS as u64, 0, 0, 0, 0, 0]), libc::SECCOMP_RET_ALLOW);
+    }
+
+    #[test]
+    fn rejects_invalid_profiles() {
+        let profile: Profile = serde_json::from_str(r#"{
+            "defaultAction": "SCMP_ACT_ERRNO",
+            "syscalls": [{ "names": ["read"], "action": "SCMP_ACT_ALLOW", "args": [{ "index": 6, "value": 0, "op": "SCMP_CMP_EQ" }] }]
+        }"#).unwrap();
+        assert!(compile_profile(&profile, &[]).is_err());
+        let profile: Profile = serde_json::from_str(r#"{ "defaultAction": "SCMP_ACT_BOGUS" }"#).unwrap();
+        assert!(compile_profile(&profile, &[]).is_err());
+    }
+}
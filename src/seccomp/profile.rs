@@ -0,0 +1,175 @@
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+
+// docker 格式的 seccomp 配置文件，只解析生成过滤器需要的字段，其余字段（如 archMap、comment）忽略
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub default_action: String,
+    #[serde(default)]
+    pub default_errno_ret: Option<u32>,
+    #[serde(default)]
+    pub syscalls: Vec<SyscallRule>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyscallRule {
+    #[serde(default)]
+    pub names: Vec<String>,
+    #[serde(default)]
+    pub name: Option<String>,       // 旧版本的配置文件每条规则只有一个系统调用
+    pub action: String,
+    #[serde(default)]
+    pub args: Vec<Arg>,
+    #[serde(default)]
+    pub errno_ret: Option<u32>,
+    #[serde(default)]
+    pub includes: Filter,
+    #[serde(default)]
+    pub excludes: Filter,
+}
+
+// 对系统调用参数的比较，MASKED_EQ 时 value 为掩码、value_two 为期望的值
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Arg {
+    pub index: u32,
+    pub value: u64,
+    #[serde(default)]
+    pub value_two: u64,
+    pub op: String,
+}
+
+// 规则生效的条件：includes 中的条件都满足、excludes 中的条件都不满足时规则才生效
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Filter {
+    #[serde(default)]
+    pub caps: Vec<String>,
+    #[serde(default)]
+    pub arches: Vec<String>,
+    #[serde(default)]
+    pub min_kernel: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Allow,
+    Errno(u32),
+    KillThread,
+    KillProcess,
+    Trap,
+    Trace(u32),
+    Log,
+}
+
+#[derive(Clone, Copy)]
+pub enum CmpOp {
+    Ne,
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+    MaskedEq,
+}
+
+const EPERM: u32 = 1;
+
+impl Action {
+    pub fn parse(action: &str, errno_ret: Option<u32>) -> Result<Self> {
+        match action {
+            "SCMP_ACT_ALLOW" => Ok(Action::Allow),
+            "SCMP_ACT_ERRNO" => Ok(Action::Errno(errno_ret.unwrap_or(EPERM))),
+            "SCMP_ACT_KILL" | "SCMP_ACT_KILL_THREAD" => Ok(Action::KillThread),
+            "SCMP_ACT_KILL_PROCESS" => Ok(Action::KillProcess),
+            "SCMP_ACT_TRAP" => Ok(Action::Trap),
+            "SCMP_ACT_TRACE" => Ok(Action::Trace(errno_ret.unwrap_or(0))),
+            "SCMP_ACT_LOG" => Ok(Action::Log),
+            _ => Err(Error::InvalidArgument(format!("Unknown seccomp action {}", action))),
+        }
+    }
+}
+
+impl CmpOp {
+    pub fn parse(op: &str) -> Result<Self> {
+        match op {
+            "SCMP_CMP_NE" => Ok(CmpOp::Ne),
+            "SCMP_CMP_LT" => Ok(CmpOp::Lt),
+            "SCMP_CMP_LE" => Ok(CmpOp::Le),
+            "SCMP_CMP_EQ" => Ok(CmpOp::Eq),
+            "SCMP_CMP_GE" => Ok(CmpOp::Ge),
+            "SCMP_CMP_GT" => Ok(CmpOp::Gt),
+            "SCMP_CMP_MASKED_EQ" => Ok(CmpOp::MaskedEq),
+            _ => Err(Error::InvalidArgument(format!("Unknown seccomp operator {}", op))),
+        }
+    }
+}
+
+impl SyscallRule {
+    pub fn syscall_names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().chain(self.name.iter()).map(String::as_str)
+    }
+
+    // capabilities 为容器保留的 capability，arch 为 docker 中的架构名（如 amd64），kernel 为内核版本
+    pub fn applies(&self, capabilities: &[String], arch: &str, kernel: (u32, u32)) -> bool {
+        let has_cap = |cap: &String| capabilities.contains(cap);
+        let includes = &self.includes;
+        let excludes = &self.excludes;
+        includes.caps.iter().all(has_cap)
+            && (includes.arches.is_empty() || includes.arches.iter().any(|a| a == arch))
+            && includes.min_kernel.as_deref().and_then(parse_kernel).is_none_or(|min| kernel >= min)
+            && !excludes.caps.iter().any(has_cap)
+            && !excludes.arches.iter().any(|a| a == arch)
+            && excludes.min_kernel.as_deref().and_then(parse_kernel).is_none_or(|min| kernel < min)
+    }
+}
+
+// 内核版本形如 5.15.0-91-generic，只比较主次版本号
+pub fn parse_kernel(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(['.', '-']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_kernel_versions() {
+        assert_eq!(parse_kernel("5.15.0-91-generic"), Some((5, 15)));
+        assert_eq!(parse_kernel("6.8"), Some((6, 8)));
+        assert_eq!(parse_kernel("6"), None);
+        assert_eq!(parse_kernel("abc"), None);
+    }
+
+    #[test]
+    fn parses_actions() {
+        assert!(Action::parse("SCMP_ACT_ERRNO", None).unwrap() == Action::Errno(EPERM));
+        assert!(Action::parse("SCMP_ACT_ERRNO", Some(38)).unwrap() == Action::Errno(38));
+        assert!(Action::parse("SCMP_ACT_KILL", None).unwrap() == Action::KillThread);
+        assert!(Action::parse("SCMP_ACT_NOTIFY", None).is_err());
+        assert!(CmpOp::parse("SCMP_CMP_MASKED_EQ").is_ok());
+        assert!(CmpOp::parse("SCMP_CMP_IN").is_err());
+    }
+
+    #[test]
+    fn rule_conditions() {
+        let rule: SyscallRule = serde_json::from_str(r#"{
+            "names": ["mount"],
+            "action": "SCMP_ACT_ALLOW",
+            "includes": { "caps": ["CAP_SYS_ADMIN"], "minKernel": "4.8" },
+            "excludes": { "arches": ["s390x"] }
+        }"#).unwrap();
+        let admin = vec!["CAP_SYS_ADMIN".to_string()];
+        assert!(rule.applies(&admin, "amd64", (5, 15)));
+        assert!(!rule.applies(&[], "amd64", (5, 15)));
+        assert!(!rule.applies(&admin, "s390x", (5, 15)));
+        assert!(!rule.applies(&admin, "amd64", (4, 4)));
+        assert_eq!(rule.syscall_names().collect::<Vec<_>>(), ["mount"]);
+    }
+}
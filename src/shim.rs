@@ -0,0 +1,134 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
+
+use log::{error, info};
+use nix::fcntl::{open, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::{dup2, fork, pipe2, setsid, ForkResult, Pid};
+
+use crate::RunCommand;
+use crate::cgroupsv2::CGroupManager;
+use crate::container::{
+    decode_result, delete_workspace, encode_result, record_exit, serve_console, Console, ExitStatus,
+};
+use crate::network;
+use crate::error::{Context, Error, Result};
+use crate::process;
+use crate::run::create_container;
+use crate::state::metainfo_base_path;
+
+// 后台运行的容器由一个常驻的 shim 进程管理：
+// shim 由 run fork 出来并脱离 mydocker 的会话，容器进程由 shim clone 出来，因此 shim 是容器的父进程，
+// 负责转发容器的标准输入输出、回收容器进程、记录退出状态并清理 cgroup、overlayfs 和网络
+pub fn spawn_shim(command: RunCommand, container_id: String) -> Result<()> {
+    // shim 通过管道把容器的启动结果发给 mydocker，格式与容器 init 进程上报的相同
+    let (ready_read, ready_write) = pipe2(OFlag::O_CLOEXEC).context("Failed to create pipe")?;
+    match unsafe { fork() }.context(format!("Failed to fork shim for container {}", container_id))? {
+        ForkResult::Parent { child } => {
+            drop(ready_write);
+            info!("Shim for container {} started with PID {}", container_id, child);
+            // 容器 execvp 之后才会上报结果，此时只有 shim 还持有写端，可以一直读到 EOF
+            let mut ready = vec![];
+            File::from(ready_read).read_to_end(&mut ready).context("Failed to read from shim")?;
+            if ready.is_empty() {
+                return Err(Error::Runtime(format!("Shim of container {} exited unexpectedly", container_id)));
+            }
+            decode_result(&ready)?;
+            println!("{}", container_id);
+            Ok(())
+        }
+        ForkResult::Child => {
+            drop(ready_read);
+            let code = run_shim(command, container_id, ready_write);
+            std::process::exit(code);
+        }
+    }
+}
+
+fn run_shim(command: RunCommand, container_id: String, ready: OwnedFd) -> i32 {
+    // 新建会话，用户的终端关闭时 shim 不会收到 SIGHUP
+    let _ = setsid();
+
+    let mut ready = File::from(ready);
+    let started = Console::new(command.tty, command.interactive)
+        .context("Failed to create console")
+        .and_then(|console| {
+            let pid = create_container(&command, &container_id, Some(console.stdio()))?;
+            Ok((pid, console.into_host()))
+        });
+    let (pid, io) = match started {
+        Ok(started) => started,
+        Err(e) => {
+            let code = e.exit_code();
+            let _ = ready.write_all(&encode_result(&Err(e)));
+            return code;
+        }
+    };
+
+    // 容器启动之前 shim 的输出仍然是用户的终端，出错时用户能直接看到
+    // 通知 mydocker 之后，shim 的输出改写到 shim.log 中
+    let _ = ready.write_all(&encode_result(&Ok(())));
+    drop(ready);
+    redirect_output(&container_id);
+
+    let console_id = container_id.clone();
+    let console_thread = std::thread::spawn(move || serve_console(&console_id, io));
+    supervise(&container_id, pid, &command);
+    let _ = console_thread.join();
+    0
+}
+
+fn redirect_output(container_id: &str) {
+    if let Ok(null) = open("/dev/null", OFlag::O_RDONLY, Mode::empty()) {
+        let _ = dup2(null, 0);
+    }
+    let log_path = format!("{}{}/shim.log", metainfo_base_path(), container_id);
+    match OpenOptions::new().append(true).create(true).open(&log_path) {
+        Ok(file) => {
+            let _ = dup2(file.as_raw_fd(), 1);
+            let _ = dup2(file.as_raw_fd(), 2);
+        }
+        Err(_) => {
+            if let Ok(null) = open("/dev/null", OFlag::O_WRONLY, Mode::empty()) {
+                let _ = dup2(null, 1);
+                let _ = dup2(null, 2);
+            }
+        }
+    }
+}
+
+// 等待容器进程退出，清理容器占用的资源并记录退出码，返回容器的退出码
+// 后台容器由 shim 调用，前台容器由 run 自己调用
+pub fn supervise(container_id: &str, pid: i32, command: &RunCommand) -> i32 {
+    let (code, signal) = process::wait(Pid::from_raw(pid));
+    let signal = signal.map(|signal| signal.as_str().to_string());
+    info!("Container {} exited with code {}", container_id, code);
+    let oom_killed = cleanup_container(container_id, command);
+    if let Err(e) = record_exit(container_id, ExitStatus { code, signal, oom_killed }) {
+        error!("Failed to record exit status of container {}: {}", container_id, e);
+    }
+    code
+}
+
+// 清理容器占用的资源，返回容器是否被 OOM killer 杀死过
+// 尽力而为：某一项清理失败时记录日志，继续清理其余的资源
+pub fn cleanup_container(container_id: &str, command: &RunCommand) -> bool {
+    let cgroupv2_manager = CGroupManager::new(container_id.to_string());
+    let oom_killed = cgroupv2_manager.check_cgroup_memory_events(); // 检查 cgroup 内存事件
+    if let Err(e) = cgroupv2_manager.destroy_cgroup() {
+        error!("{}", e);
+    }
+
+    if command.net.is_some()
+        && let Err(e) = network::disconnect(container_id)
+    {
+        error!("{}", e);
+    }
+
+    // 删除 overlayfs 的工作空间
+    if let Err(e) = delete_workspace(container_id, command.volume.as_deref()) {
+        error!("{}", e);
+    }
+    oom_killed
+}
@@ -1,15 +1,15 @@
 use crate::StartCommand;
 use crate::run::run_container;
-use crate::container::{is_running, get_command};
+use crate::container::{is_running, get_command, resolve_container_id};
+use crate::error::{Error, Result};
 
-pub fn start(command: StartCommand) {
-    let container_id = command.container_id.clone();
+pub fn start(command: StartCommand) -> Result<i32> {
+    let container_id = resolve_container_id(&command.container_id)?;
     // 检查容器是否已经在运行
-    if is_running(&container_id) {
-        println!("Container {} is already running", container_id);
-        return;
+    if is_running(&container_id)? {
+        return Err(Error::Conflict(format!("Container {} is already running", container_id)));
     }
 
-    let run_command = get_command(&container_id);
-    run_container(run_command.clone(), container_id.clone());
+    let run_command = get_command(&container_id)?;
+    run_container(run_command, container_id)
 }
\ No newline at end of file
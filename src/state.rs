@@ -0,0 +1,108 @@
+use std::path::{Component, Path};
+
+use nix::unistd::geteuid;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+
+use crate::error::{Context, Error, Result};
+
+// 各个选项的默认值，命令行参数和环境变量优先于配置文件
+const CONFIG_FILE: &str = "/etc/mydocker/config.json";
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    root: Option<String>,
+    cgroup_parent: Option<String>,
+}
+
+// 镜像、容器、网络等状态保存的根目录，可以通过 --root、MYDOCKER_ROOT 或配置文件指定
+// 默认 root 用户使用 /root/.mydocker；普通用户以 rootless 模式运行，状态保存在 $XDG_DATA_HOME/mydocker
+static STATE_ROOT: OnceCell<String> = OnceCell::new();
+// 容器 cgroup 的父目录，是相对于 /sys/fs/cgroup 的路径，为 None 时使用默认的目录
+static CGROUP_PARENT: OnceCell<Option<String>> = OnceCell::new();
+
+// 在执行子命令之前调用，参数为命令行或环境变量中指定的值
+pub fn init(root: Option<String>, cgroup_parent: Option<String>) -> Result<()> {
+    let config = load_config()?;
+    if let Some(root) = root.or(config.root).filter(|root| !root.is_empty()) {
+        // 容器进程和 shim 会切换工作目录，统一转换为绝对路径
+        let root = std::path::absolute(&root).context(format!("Invalid root directory {}", root))?;
+        let _ = STATE_ROOT.set(root.to_string_lossy().trim_end_matches('/').to_string());
+    }
+
+    let cgroup_parent = cgroup_parent.or(config.cgroup_parent).filter(|parent| !parent.is_empty());
+    if let Some(parent) = &cgroup_parent
+        && Path::new(parent).components().any(|c| c == Component::ParentDir)
+    {
+        return Err(Error::InvalidArgument(format!("Invalid cgroup parent {}: must not contain ..", parent)));
+    }
+    let _ = CGROUP_PARENT.set(cgroup_parent.map(|parent| parent.trim_matches('/').to_string()));
+    Ok(())
+}
+
+fn load_config() -> Result<Config> {
+    let content = match std::fs::read_to_string(CONFIG_FILE) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(e).context(format!("Failed to read {}", CONFIG_FILE)),
+    };
+    serde_json::from_str(&content).context(format!("Failed to parse {}", CONFIG_FILE))
+}
+
+pub fn cgroup_parent() -> Option<&'static str> {
+    CGROUP_PARENT.get().and_then(|parent| parent.as_deref())
+}
+
+// 非 root 用户运行 mydocker 时为 rootless 模式：容器总是运行在新的 user namespace 中，不能使用 bridge 网络
+pub fn is_rootless() -> bool {
+    !geteuid().is_root()
+}
+
+pub fn state_root() -> &'static str {
+    STATE_ROOT.get_or_init(|| {
+        if !is_rootless() {
+            return "/root/.mydocker".to_string();
+        }
+        let data_home = match std::env::var("XDG_DATA_HOME") {
+            Ok(dir) if !dir.is_empty() => dir,
+            _ => match std::env::var("HOME") {
+                Ok(home) if !home.is_empty() => format!("{}/.local/share", home),
+                _ => format!("/tmp/mydocker-{}", geteuid()),
+            },
+        };
+        format!("{}/mydocker", data_home)
+    })
+}
+
+// 镜像存储路径
+pub fn image_base_path() -> String {
+    format!("{}/image/", state_root())
+}
+
+// 镜像以 OverlayFS 的形式 mount 的位置
+pub fn rootfs_base_path() -> String {
+    format!("{}/overlay2/", state_root())
+}
+
+// 容器的元信息、日志和 attach socket
+pub fn metainfo_base_path() -> String {
+    format!("{}/containers/", state_root())
+}
+
+// 已经占用的容器名和短 ID，创建容器时先以它们为文件名创建文件，避免同时创建的容器重名
+pub fn names_base_path() -> String {
+    format!("{}/names/", state_root())
+}
+
+pub fn short_ids_base_path() -> String {
+    format!("{}/ids/", state_root())
+}
+
+pub fn network_file() -> String {
+    format!("{}/network/network/network.json", state_root())
+}
+
+pub fn ipam_file() -> String {
+    format!("{}/network/ipam/subnet.json", state_root())
+}
@@ -1,40 +1,53 @@
-use log::{error, info};
+use log::info;
 use nix::sys::signal::{kill, Signal};
-use nix::unistd::{sleep, Pid};
+use nix::unistd::Pid;
+use std::time::Duration;
 
 use crate::StopCommand;
-use crate::container::{delete_workspace, get_pid, get_volume, is_running, record_exit};
-use crate::cgroupsv2::CGroupManager;
+use crate::container::{get_pid, is_running, is_supervised, reap_container, resolve_container_id, ExitStatus};
+use crate::error::{Context, Error, Result};
 
-pub fn stop(command: StopCommand) {
-    let container_id = command.container_id.clone();
-    if !is_running(&container_id) {
-        error!("Container {} is not running", container_id);
-        return;
+pub fn stop(command: StopCommand) -> Result<()> {
+    let container_id = resolve_container_id(&command.container_id)?;
+    if !is_running(&container_id)? {
+        return Err(Error::Conflict(format!("Container {} is not running", container_id)));
     }
-    let pid = get_pid(&container_id);
+    let pid = Pid::from_raw(get_pid(&container_id)? as i32);
     info!("Stopping container {} with PID {}", container_id, pid);
     // 使用 SIGTERM 信号停止容器
-    if let Err(e) = kill(Pid::from_raw(pid as i32), Signal::SIGTERM) {
-        error!("Failed to stop container {}: {}", container_id, e);
-        return;
-    }
+    kill(pid, Signal::SIGTERM).context(format!("Failed to stop container {}", container_id))?;
 
-    sleep(5);
-    // 检查容器是否已经停止
-    if let Ok(_) = kill(Pid::from_raw(pid as i32), None) {
-        kill(Pid::from_raw(pid as i32), Signal::SIGKILL).unwrap();
+    // 最多等待 5 秒，容器仍未退出则强制杀死
+    let mut signal = Signal::SIGTERM;
+    if !wait_until(Duration::from_secs(5), || kill(pid, None).is_err()) {
+        kill(pid, Signal::SIGKILL).context(format!("Failed to kill container {}", container_id))?;
+        signal = Signal::SIGKILL;
         info!("Container process {} is still running, forcefully killed", pid);
+        wait_until(Duration::from_secs(5), || kill(pid, None).is_err());
     }
 
-    // 删除容器 overlayfs 目录
-    let volume = get_volume(&container_id);
-    delete_workspace(&container_id, volume.as_deref());
-
-    // 删除容器 cgroup 目录
-    let cgroupv2_manager = CGroupManager::new(container_id.clone());
-    cgroupv2_manager.destroy_cgroup();
+    // 容器进程的 supervisor（shim 或前台的 run）负责回收容器、清理资源并记录 exited 状态
+    if wait_until(Duration::from_secs(5), || !is_supervised(&container_id)) {
+        // supervisor 已经不在了但没有记录 exited 状态时，由 stop 自己完成清理
+        return reap_container(&container_id, ExitStatus {
+            code: 128 + signal as i32,
+            signal: Some(signal.as_str().to_string()),
+            oom_killed: false,
+        });
+    }
+    info!("Supervisor of container {} has not finished cleaning up yet", container_id);
+    Ok(())
+}
 
-    // 记录容器 exited 状态
-    record_exit(&container_id);
-}
\ No newline at end of file
+fn wait_until(timeout: Duration, mut done: impl FnMut() -> bool) -> bool {
+    let step = Duration::from_millis(100);
+    let mut waited = Duration::ZERO;
+    while waited < timeout {
+        if done() {
+            return true;
+        }
+        std::thread::sleep(step);
+        waited += step;
+    }
+    done()
+}